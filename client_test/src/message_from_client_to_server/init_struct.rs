@@ -1,20 +1,32 @@
 use serde::Serialize;
 
+/// Handshake opening every connection. This client speaks the JSON wire format, so
+/// the broker has to run with `BROKER_WIRE_FORMAT=json`.
 #[derive(Serialize)]
 pub struct InitProducerConsumer {
-    pub message: i32,
+    pub message: Handshake,
+}
+
+#[derive(Serialize)]
+pub struct Handshake {
+    pub client_type: String,
 }
 
 impl InitProducerConsumer {
     pub fn new_producer_message() -> Vec<u8> {
-        let message = Self { message: 0 };
-        let mut vec_data = serde_json::to_vec(&message).unwrap();
-        vec_data.push(b'\0');
-        return vec_data;
+        Self::new_message("producer")
     }
 
     pub fn new_consumer_message() -> Vec<u8> {
-        let message = Self { message: 1 };
+        Self::new_message("consumer")
+    }
+
+    fn new_message(client_type: &str) -> Vec<u8> {
+        let message = Self {
+            message: Handshake {
+                client_type: client_type.to_string(),
+            },
+        };
         let mut vec_data = serde_json::to_vec(&message).unwrap();
         vec_data.push(b'\0');
         return vec_data;
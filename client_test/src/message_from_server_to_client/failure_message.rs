@@ -1,4 +1,8 @@
 use serde::Deserialize;
 
 #[derive(Deserialize)]
-pub struct Failure {}
+pub struct Failure {
+    pub error_code: i16,
+    pub message: String,
+    pub retriable: bool,
+}
@@ -1,2 +1,3 @@
 pub mod failure_message;
+pub mod produce_ack_message;
 pub mod success_message;
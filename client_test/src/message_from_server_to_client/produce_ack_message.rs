@@ -0,0 +1,8 @@
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+pub struct ProduceAck {
+    pub partition: i32,
+    pub offset: i32,
+    pub timestamp: i64,
+}
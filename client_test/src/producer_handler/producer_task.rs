@@ -10,7 +10,7 @@ use crate::{
             CreateTopic, DeleteTopic, Message, MessageTopic, ProducerMessage,
         },
     },
-    message_from_server_to_client::success_message::Success,
+    message_from_server_to_client::{produce_ack_message::ProduceAck, success_message::Success},
 };
 
 pub async fn produce_task() -> Result<(), Box<dyn std::error::Error>> {
@@ -48,8 +48,9 @@ pub async fn produce_task() -> Result<(), Box<dyn std::error::Error>> {
         let _ = write_half.write_all(&normal_message_without_key).await;
         let _ = write_half.flush().await;
 
+        buffer.clear();
         let n = reader.read_until(b'\0', &mut buffer).await.unwrap();
-        serde_json::from_slice::<Success>(&buffer[..n - 1]).unwrap();
+        serde_json::from_slice::<ProduceAck>(&buffer[..n - 1]).unwrap();
     }
 
     for i in 1..23 {
@@ -61,8 +62,9 @@ pub async fn produce_task() -> Result<(), Box<dyn std::error::Error>> {
         let _ = write_half.write_all(&normal_message_with_key).await;
         let _ = write_half.flush().await;
 
+        buffer.clear();
         let n = reader.read_until(b'\0', &mut buffer).await.unwrap();
-        serde_json::from_slice::<Success>(&buffer[..n - 1]).unwrap();
+        serde_json::from_slice::<ProduceAck>(&buffer[..n - 1]).unwrap();
     }
 
     /*
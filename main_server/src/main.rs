@@ -1,76 +1,129 @@
+use std::env;
+use std::process;
 use std::sync::Arc;
 
-use tokio::io::AsyncWriteExt;
-use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::net::TcpListener;
 use tokio::sync::RwLock;
 
+use crate::state::config::broker_config::{BrokerConfig, FsyncPolicy, WireFormat};
 use crate::state::consumer::consumer::Consumer;
-use crate::state::message_from_client::init_struct::InitProducerConsumer;
-use crate::state::message_to_client::success_message::Success;
+use crate::state::message_from_client::handshake::{ClientType, Handshake};
+use crate::state::message_state::compaction_task::run_compaction;
+use crate::state::message_state::fsync_task::run_interval_fsync;
+use crate::state::message_state::log_verifier::verify_topic;
+use crate::state::message_state::retention_task::run_retention;
+use crate::state::message_to_client::failure_message::Failure;
+use crate::state::message_to_client::handshake_message::HandshakeMessage;
 use crate::state::producer::producer::Producer;
+use crate::state::protocol::api_keys::{self, supported_versions};
+use crate::state::protocol::broker_error::BrokerError;
+use crate::state::protocol::connection::{Request, RequestReader, ResponseWriter};
+use crate::state::topic_state::session_timeout_task::run_session_timeouts;
 use crate::state::topic_state::topic_state::Topic;
 
 pub mod state;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // `main_server verify-log <topic> [--truncate]` checks a topic's segments and exits.
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().is_some_and(|command| command == "verify-log") {
+        let Some(topic_name) = args.get(1) else {
+            eprintln!("Usage: main_server verify-log <topic> [--truncate]");
+            process::exit(2);
+        };
+        let truncate = args.iter().skip(2).any(|arg| arg == "--truncate");
+        if verify_topic(topic_name, truncate)? > 0 {
+            process::exit(1);
+        }
+        return Ok(());
+    }
     let listener = TcpListener::bind("127.0.0.1:8000").await?;
-    let topics_data = Arc::new(RwLock::new(Topic::new()));
+    let broker_config = BrokerConfig::from_env()?;
+    let mut topics = Topic::new(&broker_config);
+    topics.recover_from_disk().await?;
+    let topics_data = Arc::new(RwLock::new(topics));
+    if let FsyncPolicy::Interval(interval) = broker_config.fsync_policy {
+        tokio::spawn(run_interval_fsync(Arc::clone(&topics_data), interval));
+    }
+    tokio::spawn(run_retention(
+        Arc::clone(&topics_data),
+        broker_config.retention_check_interval,
+    ));
+    tokio::spawn(run_compaction(
+        Arc::clone(&topics_data),
+        broker_config.compaction_check_interval,
+    ));
+    tokio::spawn(run_session_timeouts(
+        Arc::clone(&topics_data),
+        broker_config.session_check_interval,
+    ));
     println!("Server listening on 127.0.0.1:8000");
 
     loop {
         let (socket, addr) = listener.accept().await?;
-        let (read_half, mut write_half) = socket.into_split();
-        let mut reader = BufReader::new(read_half);
+        let (read_half, write_half) = socket.into_split();
+        let mut reader = RequestReader::new(read_half, broker_config.wire_format);
+        let mut writer = ResponseWriter::new(write_half, broker_config.wire_format);
         println!("New connection from: {}", addr);
         let thread_topic = Arc::clone(&topics_data);
 
         tokio::spawn(async move {
-            let mut buffer = Vec::new();
-            let n = match reader.read_until(b'\0', &mut buffer).await {
-                Ok(0) => {
+            let Request { header, body } = match reader.next_request::<Handshake>().await {
+                Ok(Some(request)) => request,
+                Ok(None) => {
                     return;
                 }
-                Ok(n) => {
-                    let data = &buffer[..n - 1];
-                    match serde_json::from_slice::<InitProducerConsumer>(data) {
-                        Err(e) => {
-                            println!("Invalid data {:?}", e);
-                            return;
-                        }
-                        Ok(init_struct) => match init_struct.message {
-                            0 => {
-                                println!("producer task in");
-                                let success_message = Success::new();
-                                success_message.send_message(&mut write_half).await;
-                                let producer = Producer::new(Arc::clone(&thread_topic));
-                                producer.handler(reader, write_half).await;
-                                return;
-                            }
-                            1 => {
-                                println!("consumer task in");
-                                let success_message = Success::new();
-                                success_message.send_message(&mut write_half).await;
-                                let consumer = Consumer::new(Arc::clone(&thread_topic));
-                                consumer.handler(reader, write_half).await;
-                                return;
-                            }
-                            _ => {
-                                println!("Invalid data");
-                            }
-                        },
-                    };
-                    n
-                }
                 Err(e) => {
                     eprintln!("Failed to read from socket; err = {:?}", e);
                     return;
                 }
             };
-            if let Err(e) = write_half.write_all(&buffer[..n]).await {
-                eprintln!("Failed to write to socket; err = {:?}", e);
-                return;
+            let handshake = match body {
+                Ok(_)
+                    if broker_config.wire_format == WireFormat::Binary
+                        && header.api_key != api_keys::HANDSHAKE =>
+                {
+                    println!("Invalid data, expected a handshake");
+                    Failure::new(&BrokerError::invalid_request("expected a handshake"))
+                        .send_message(&mut writer, &header)
+                        .await;
+                    return;
+                }
+                Ok(handshake) => handshake,
+                Err(error) => {
+                    println!("Invalid handshake");
+                    // Tell the client which versions it could have used.
+                    if let BrokerError::UnsupportedVersion { .. } = error {
+                        writer
+                            .send(
+                                &header,
+                                error.code(),
+                                &HandshakeMessage::new(supported_versions()),
+                            )
+                            .await;
+                    } else {
+                        Failure::new(&error)
+                            .send_message(&mut writer, &header)
+                            .await;
+                    }
+                    return;
+                }
+            };
+            HandshakeMessage::new(supported_versions())
+                .send_message(&mut writer, &header)
+                .await;
+            match handshake.client_type {
+                ClientType::Producer => {
+                    println!("producer task in");
+                    let producer = Arc::new(Producer::new(Arc::clone(&thread_topic)));
+                    producer.handler(reader, writer).await;
+                }
+                ClientType::Consumer => {
+                    println!("consumer task in");
+                    let consumer = Arc::new(Consumer::new(Arc::clone(&thread_topic)));
+                    consumer.handler(reader, writer).await;
+                }
             }
         });
     }
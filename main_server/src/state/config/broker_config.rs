@@ -0,0 +1,94 @@
+use std::{env, time::Duration};
+
+/// When appended records are forced from the OS page cache to disk.
+#[derive(Debug, Clone, Copy)]
+pub enum FsyncPolicy {
+    /// fsync after every record, before the producer is acknowledged.
+    EveryMessage,
+    /// fsync dirty segments from a background task every interval.
+    Interval(Duration),
+    /// Never fsync explicitly and let the OS write pages back.
+    OsManaged,
+}
+
+/// How requests and responses are encoded on client connections.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WireFormat {
+    /// Length-prefixed binary frames, see `protocol::frame`.
+    Binary,
+    /// NUL-terminated JSON, for debugging with plain text tools.
+    Json,
+}
+
+#[derive(Debug, Clone)]
+pub struct BrokerConfig {
+    pub fsync_policy: FsyncPolicy,
+    pub wire_format: WireFormat,
+    /// How often the retention task looks for segments to delete.
+    pub retention_check_interval: Duration,
+    /// How often the compactor looks for compacted topics to clean.
+    pub compaction_check_interval: Duration,
+    /// Session timeout of consumer groups that do not set their own.
+    pub consumer_session_timeout: Duration,
+    /// Max poll interval of consumer groups that do not set their own.
+    pub consumer_max_poll_interval: Duration,
+    /// How often consumers are checked for expired sessions.
+    pub session_check_interval: Duration,
+}
+
+impl BrokerConfig {
+    /// Reads the broker settings from the environment.
+    ///
+    /// `BROKER_FSYNC_POLICY` is one of `every_message` (default), `interval` or `os`;
+    /// with `interval` the period is taken from `BROKER_FSYNC_INTERVAL_MS` (default 1000).
+    /// `BROKER_RETENTION_CHECK_INTERVAL_MS` sets how often retention runs (default 300000)
+    /// and `BROKER_COMPACTION_CHECK_INTERVAL_MS` how often compaction does (default 60000).
+    /// `BROKER_CONSUMER_SESSION_TIMEOUT_MS` (default 10000) and
+    /// `BROKER_CONSUMER_MAX_POLL_INTERVAL_MS` (default 300000) are the consumer group
+    /// defaults, enforced every `BROKER_SESSION_CHECK_INTERVAL_MS` (default 1000).
+    /// `BROKER_WIRE_FORMAT` is `binary` (default) or `json`.
+    pub fn from_env() -> Result<Self, String> {
+        let fsync_policy = match env::var("BROKER_FSYNC_POLICY").as_deref() {
+            Err(_) | Ok("every_message") => FsyncPolicy::EveryMessage,
+            Ok("interval") => {
+                FsyncPolicy::Interval(duration_from_env("BROKER_FSYNC_INTERVAL_MS", 1000)?)
+            }
+            Ok("os") => FsyncPolicy::OsManaged,
+            Ok(other) => return Err(format!("Invalid BROKER_FSYNC_POLICY {:?}", other)),
+        };
+        let wire_format = match env::var("BROKER_WIRE_FORMAT").as_deref() {
+            Err(_) | Ok("binary") => WireFormat::Binary,
+            Ok("json") => WireFormat::Json,
+            Ok(other) => return Err(format!("Invalid BROKER_WIRE_FORMAT {:?}", other)),
+        };
+        let retention_check_interval =
+            duration_from_env("BROKER_RETENTION_CHECK_INTERVAL_MS", 300_000)?;
+        let compaction_check_interval =
+            duration_from_env("BROKER_COMPACTION_CHECK_INTERVAL_MS", 60_000)?;
+        let consumer_session_timeout =
+            duration_from_env("BROKER_CONSUMER_SESSION_TIMEOUT_MS", 10_000)?;
+        let consumer_max_poll_interval =
+            duration_from_env("BROKER_CONSUMER_MAX_POLL_INTERVAL_MS", 300_000)?;
+        let session_check_interval = duration_from_env("BROKER_SESSION_CHECK_INTERVAL_MS", 1000)?;
+        Ok(Self {
+            fsync_policy,
+            wire_format,
+            retention_check_interval,
+            compaction_check_interval,
+            consumer_session_timeout,
+            consumer_max_poll_interval,
+            session_check_interval,
+        })
+    }
+}
+
+/// Reads a positive number of milliseconds from `name`, `default_ms` when unset.
+fn duration_from_env(name: &str, default_ms: u64) -> Result<Duration, String> {
+    let millis = match env::var(name) {
+        Err(_) => default_ms,
+        Ok(value) => value
+            .parse::<u64>()
+            .map_err(|_| format!("Invalid {} {:?}", name, value))?,
+    };
+    Ok(Duration::from_millis(millis.max(1)))
+}
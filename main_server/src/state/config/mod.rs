@@ -0,0 +1,2 @@
+pub mod broker_config;
+pub mod topic_config;
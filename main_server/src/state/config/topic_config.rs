@@ -0,0 +1,65 @@
+use serde::{Deserialize, Serialize};
+
+/// What happens to closed segments of a topic once their records are no longer needed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CleanupPolicy {
+    /// Whole segments are deleted according to `retention_ms` and `retention_bytes`.
+    Delete,
+    /// Closed segments are rewritten to keep only the latest record of every key.
+    Compact,
+}
+
+/// Per-topic settings, given on `CREATETOPIC` and persisted next to the topic's
+/// partitions as `logs/<topic>/config.json`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct TopicConfig {
+    /// Roll the active segment once appending would grow it past this many bytes.
+    pub segment_bytes: u64,
+    /// Roll the active segment once it holds this many records.
+    pub segment_records: i32,
+    /// Roll the active segment once it is older than this many milliseconds.
+    pub segment_ms: i64,
+    /// Add an entry to the segment's offset index every time this many bytes of
+    /// records were appended since the previous entry.
+    pub index_interval_bytes: u64,
+    /// Delete closed segments whose newest record is older than this many
+    /// milliseconds; `-1` keeps them forever.
+    pub retention_ms: i64,
+    /// Delete the oldest closed segments while the partition would still hold at
+    /// least this many bytes without them; `-1` means no size limit.
+    pub retention_bytes: i64,
+    /// `delete` or `compact`; retention settings are ignored by compacted topics.
+    pub cleanup_policy: CleanupPolicy,
+    /// How long a tombstone (a record with a null value) is kept by compaction so
+    /// that consumers get to see the delete, in milliseconds.
+    pub delete_retention_ms: i64,
+}
+
+impl Default for TopicConfig {
+    fn default() -> Self {
+        Self {
+            segment_bytes: 64 * 1024 * 1024,
+            segment_records: i32::MAX,
+            segment_ms: 7 * 24 * 60 * 60 * 1000,
+            index_interval_bytes: 4096,
+            retention_ms: 7 * 24 * 60 * 60 * 1000,
+            retention_bytes: -1,
+            cleanup_policy: CleanupPolicy::Delete,
+            delete_retention_ms: 24 * 60 * 60 * 1000,
+        }
+    }
+}
+
+impl TopicConfig {
+    /// Settings of a topic from before topics had a `config.json`. Its records were
+    /// kept forever, so they still are; the retention default only applies to
+    /// topics created since.
+    pub fn without_config_file() -> Self {
+        Self {
+            retention_ms: -1,
+            ..Self::default()
+        }
+    }
+}
@@ -1,18 +1,28 @@
 use std::sync::Arc;
 
 use tokio::{
-    io::{AsyncBufReadExt, BufReader},
-    net::tcp::{OwnedReadHalf, OwnedWriteHalf},
-    sync::RwLock,
+    sync::{Mutex, RwLock, mpsc, oneshot},
+    time::{Duration, Instant, timeout_at},
 };
 
 use crate::state::{
+    consumer::subscription::Subscription,
     helpers::helper::Helper,
     message_from_client::message_for_consumer::message::{
-        CommitOffset, ConsumerMessage, GetOffsetMessage, JoinConsumer, LeaveConsumer,
+        CommitOffset, Credit, DescribeAssignment, GetOffsetMessage, Heartbeat, JoinConsumer,
+        LeaveConsumer, Message, OffsetForTimestamp, Subscribe,
     },
     message_to_client::{
-        failure_message::Failure, offset_message::OffsetMessage, success_message::Success,
+        assignment_message::AssignmentMessage, failure_message::Failure,
+        fetch_message::FetchMessage, offset_message::OffsetMessage, poll_message::PollMessage,
+        rebalance_message::RebalanceMessage, success_message::Success,
+        timestamp_offset_message::TimestampOffsetMessage,
+    },
+    protocol::{
+        broker_error::BrokerError,
+        connection::{Request, RequestReader, ResponseWriter},
+        frame::RequestHeader,
+        ordered_executor::OrderedExecutor,
     },
     topic_state::topic_state::Topic,
 };
@@ -21,6 +31,8 @@ pub struct Consumer {
     pub topics_data: Arc<RwLock<Topic>>,
     pub id: String,
     pub helper: Helper,
+    /// The topic subscribed to with `SUBSCRIBE`, and the subscription itself.
+    pub subscription: Mutex<Option<(String, Subscription)>>,
 }
 
 impl Consumer {
@@ -30,98 +42,337 @@ impl Consumer {
             id: helper.generate_unique_id(),
             helper,
             topics_data,
+            subscription: Mutex::new(None),
         }
     }
 
-    pub async fn handler(&self, mut reader: BufReader<OwnedReadHalf>, mut writer: OwnedWriteHalf) {
-        let mut buffer = Vec::new();
+    /// Reads requests until the client leaves or disconnects, without waiting for
+    /// earlier ones to finish. Requests on a consumer's group are ordered per topic,
+    /// while reads run concurrently. A leave waits for the requests sent before it.
+    pub async fn handler(self: Arc<Self>, mut reader: RequestReader, writer: ResponseWriter) {
+        let mut executor = OrderedExecutor::new();
         loop {
-            buffer.clear();
-            match reader.read_until(b'\0', &mut buffer).await {
-                Ok(0) => {
+            match reader.next_request::<Message>().await {
+                Ok(None) => {
+                    self.subscription.lock().await.take();
                     {
                         let mut topic_guard = self.topics_data.write().await;
                         topic_guard.disconnect_user(&self.id);
                     }
                     return;
                 }
-                Ok(n) => {
-                    let message = serde_json::from_slice::<ConsumerMessage>(&buffer[..n]);
-                    match message {
-                        Err(_) => {
-                            Failure::new().send_message(&mut writer).await;
-                            continue;
+                Ok(Some(Request { header, body })) => {
+                    let mut writer = writer.clone();
+                    match body {
+                        Err(error) => {
+                            Failure::new(&error)
+                                .send_message(&mut writer, &header)
+                                .await;
                         }
-                        Ok(message) => match message.message {
-                            crate::state::message_from_client::message_for_consumer::message::Message::JOINCONSUMER(join_consumer_message) => {
-                                let JoinConsumer {topic_name} = join_consumer_message;
-                                let res;
-                                {
-                                    let mut topic_guard = self.topics_data.write().await;
-                                    res = topic_guard.add_consumer(&self.id, &topic_name);
-                                }
-                                if res == -1 {
-                                    Failure::new().send_message(&mut writer).await;
-                                } else {
-                                    Success::new().send_message(&mut writer).await;
-                                }
-                            },
-                            crate::state::message_from_client::message_for_consumer::message::Message::LEAVECONSUMER(leave_consumer) => {
-                                let LeaveConsumer {topic_name} = leave_consumer;
-                                {
-                                    let mut topic_guard = self.topics_data.write().await;
-                                    topic_guard.leave_consumer(&self.id, &topic_name);
-                                }
-                                Success::new().send_message(&mut writer).await;
+                        Ok(Message::LEAVECONSUMER(leave_consumer)) => {
+                            let (left, has_left) = oneshot::channel();
+                            let consumer = Arc::clone(&self);
+                            executor
+                                .spawn([leave_consumer.topic_name.clone()], async move {
+                                    let _ = left
+                                        .send(consumer.leave(header, leave_consumer, writer).await);
+                                })
+                                .await;
+                            if has_left.await.unwrap_or(false) {
+                                // Requests on other topics sent before the leave are
+                                // still answered.
+                                executor.finish().await;
+                                self.subscription.lock().await.take();
                                 return;
-                            },
-                            crate::state::message_from_client::message_for_consumer::message::Message::GETOFFSETMESSAGE(get_offset_message) => {
-                                let GetOffsetMessage {
-                                    topic_name,
-                                    partition,
-                                    offset,
-                                } = get_offset_message;
-                                let message;
-                                {
-                                    let topic_guard = self.topics_data.read().await;
-                                    message = topic_guard
-                                        .read_message_from_topic_and_partition(
-                                            &topic_name,
-                                            &partition,
-                                            offset
-                                        ).await;
-                                }
-                                if message.is_none() {
-                                    Failure::new().send_message(&mut writer).await;
-                                } else {
-                                    OffsetMessage::new(message.unwrap()).send_message(&mut writer).await;
-                                }
-                            },
-                            crate::state::message_from_client::message_for_consumer::message::Message::COMMITOFFSET(commit_offset) => {
-                                let CommitOffset {
-                                    topic_name,
-                                    partition,
-                                    offset
-                                } = commit_offset;
-                                let response;
-                                {
-                                    let topic_guard = self.topics_data.write().await;
-                                    response = topic_guard.messages_store.commit_offset(&partition, &topic_name,offset).await;
-                                }
-                                if response.is_err() {
-                                    Failure::new().send_message(&mut writer).await;
-                                } else {
-                                    Success::new().send_message(&mut writer).await;
-                                }
                             }
-                        },
+                        }
+                        Ok(message) => {
+                            let ordering_keys = Self::ordering_keys(&message);
+                            let consumer = Arc::clone(&self);
+                            executor
+                                .spawn(ordering_keys, async move {
+                                    consumer.handle_request(header, message, writer).await;
+                                })
+                                .await;
+                        }
                     }
                 }
                 Err(e) => {
                     eprintln!("Failed to read from socket; err = {:?}", e);
+                    self.subscription.lock().await.take();
+                    {
+                        let mut topic_guard = self.topics_data.write().await;
+                        topic_guard.disconnect_user(&self.id);
+                    }
                     return;
                 }
             };
         }
     }
+
+    fn ordering_keys(message: &Message) -> Vec<String> {
+        let topic_name = match message {
+            Message::JOINCONSUMER(message) => &message.topic_name,
+            Message::SUBSCRIBE(message) => &message.topic_name,
+            Message::CREDIT(message) => &message.topic_name,
+            Message::POLL(message) => &message.topic_name,
+            Message::HEARTBEAT(message) => &message.topic_name,
+            Message::REVOKEPARTITIONS(message) => &message.topic_name,
+            Message::DESCRIBEASSIGNMENT(message) => &message.topic_name,
+            Message::LEAVECONSUMER(message) => &message.topic_name,
+            // A commit has to land before a revoke or a leave sent after it.
+            Message::COMMITOFFSET(message) => {
+                return vec![
+                    message.topic_name.clone(),
+                    format!("{}/{}", message.topic_name, message.partition),
+                ];
+            }
+            Message::GETOFFSETMESSAGE(_) | Message::OFFSETFORTIMESTAMP(_) | Message::FETCH(_) => {
+                return Vec::new();
+            }
+        };
+        vec![topic_name.clone()]
+    }
+
+    /// Takes the consumer out of the topic's group, answering the request; whether
+    /// it left and the connection is to be closed.
+    async fn leave(
+        &self,
+        header: RequestHeader,
+        leave_consumer: LeaveConsumer,
+        mut writer: ResponseWriter,
+    ) -> bool {
+        let LeaveConsumer { topic_name } = leave_consumer;
+        let res;
+        {
+            let mut topic_guard = self.topics_data.write().await;
+            res = topic_guard.leave_consumer(&self.id, &topic_name);
+        }
+        match res {
+            Ok(()) => {
+                Success::new().send_message(&mut writer, &header).await;
+                true
+            }
+            Err(e) => {
+                Failure::new(&e).send_message(&mut writer, &header).await;
+                false
+            }
+        }
+    }
+
+    async fn handle_request(
+        &self,
+        header: RequestHeader,
+        message: Message,
+        mut writer: ResponseWriter,
+    ) {
+        match message {
+                crate::state::message_from_client::message_for_consumer::message::Message::JOINCONSUMER(join_consumer_message) => {
+                    let (events, mut received_events) = mpsc::unbounded_channel();
+                    let res;
+                    {
+                        let mut topic_guard = self.topics_data.write().await;
+                        res = topic_guard
+                            .add_consumer(&self.id, &join_consumer_message, header.api_version >= 2, events)
+                            .and_then(|()| topic_guard.describe_assignment(&self.id, &join_consumer_message.topic_name));
+                        // The assignment already covers the join's own rebalance.
+                        while received_events.try_recv().is_ok() {}
+                    }
+                    match res {
+                        Ok(assignment) => {
+                            let JoinConsumer { topic_name, group_id, .. } = join_consumer_message;
+                            AssignmentMessage::new(topic_name.clone(), assignment).send_message(&mut writer, &header).await;
+                            // Forwarded from here on, so the first one goes out after
+                            // the join's answer. Ends once the consumer leaves the group.
+                            let topics_data = Arc::clone(&self.topics_data);
+                            tokio::spawn(async move {
+                                while let Some(event) = received_events.recv().await {
+                                    let committed_offsets;
+                                    {
+                                        let topic_guard = topics_data.read().await;
+                                        committed_offsets = topic_guard.committed_offsets(&topic_name, &group_id, &event.partitions);
+                                    }
+                                    RebalanceMessage::new(topic_name.clone(), event, committed_offsets)
+                                        .send_message(&mut writer, &header)
+                                        .await;
+                                }
+                            });
+                        }
+                        Err(e) => Failure::new(&e).send_message(&mut writer, &header).await,
+                    }
+                },
+                // Leaving ends the connection, so it is handled by `leave`.
+                crate::state::message_from_client::message_for_consumer::message::Message::LEAVECONSUMER(_) => {}
+                crate::state::message_from_client::message_for_consumer::message::Message::GETOFFSETMESSAGE(get_offset_message) => {
+                    let GetOffsetMessage {
+                        topic_name,
+                        partition,
+                        offset,
+                        decompress,
+                    } = get_offset_message;
+                    let message;
+                    {
+                        let topic_guard = self.topics_data.read().await;
+                        message = topic_guard
+                            .read_message_from_topic_and_partition(
+                                &topic_name,
+                                &partition,
+                                offset,
+                                decompress
+                            ).await;
+                    }
+                    match message {
+                        Ok(record) => OffsetMessage::new(record).send_message(&mut writer, &header).await,
+                        Err(e) => {
+                            eprintln!("Failed to read offset {} of {}/{}; err = {:?}", offset, topic_name, partition, e);
+                            Failure::new(&e).send_message(&mut writer, &header).await;
+                        }
+                    }
+                },
+                crate::state::message_from_client::message_for_consumer::message::Message::COMMITOFFSET(commit_offset) => {
+                    let CommitOffset {
+                        topic_name,
+                        partition,
+                        offset,
+                        generation
+                    } = commit_offset;
+                    let response;
+                    {
+                        let mut topic_guard = self.topics_data.write().await;
+                        response = topic_guard.commit_offset(&self.id, &topic_name, &partition, offset, generation).await;
+                    }
+                    match response {
+                        Ok(()) => Success::new().send_message(&mut writer, &header).await,
+                        Err(e) => Failure::new(&e).send_message(&mut writer, &header).await,
+                    }
+                },
+                crate::state::message_from_client::message_for_consumer::message::Message::OFFSETFORTIMESTAMP(offset_for_timestamp) => {
+                    let OffsetForTimestamp {
+                        topic_name,
+                        partition,
+                        timestamp
+                    } = offset_for_timestamp;
+                    let response;
+                    {
+                        let topic_guard = self.topics_data.read().await;
+                        response = topic_guard
+                            .offset_for_timestamp(&topic_name, &partition, timestamp)
+                            .await;
+                    }
+                    match response {
+                        Err(e) => Failure::new(&e).send_message(&mut writer, &header).await,
+                        Ok(offset) => TimestampOffsetMessage::new(offset).send_message(&mut writer, &header).await,
+                    }
+                }
+                crate::state::message_from_client::message_for_consumer::message::Message::FETCH(fetch) => {
+                    let deadline = Instant::now() + Duration::from_millis(fetch.max_wait_ms.max(0) as u64);
+                    let response = loop {
+                        let new_data;
+                        {
+                            let topic_guard = self.topics_data.read().await;
+                            new_data = topic_guard.new_data_notify(&fetch.topic_name, fetch.partition);
+                        }
+                        let new_data = match new_data {
+                            Ok(new_data) => new_data,
+                            Err(e) => break Err(e),
+                        };
+                        // Created before fetching, so an append right after the fetch
+                        // still wakes this request up.
+                        let notified = new_data.notified();
+                        let response;
+                        {
+                            let topic_guard = self.topics_data.read().await;
+                            response = topic_guard.fetch(&fetch).await;
+                        }
+                        match &response {
+                            Ok(fetched) if !fetched.is_enough(fetch.min_bytes) && Instant::now() < deadline => {}
+                            _ => break response,
+                        }
+                        if timeout_at(deadline, notified).await.is_err() {
+                            let topic_guard = self.topics_data.read().await;
+                            break topic_guard.fetch(&fetch).await;
+                        }
+                    };
+                    match response {
+                        Ok(fetched) => FetchMessage::new(fetched).send_message(&mut writer, &header).await,
+                        Err(e) => Failure::new(&e).send_message(&mut writer, &header).await,
+                    }
+                }
+                crate::state::message_from_client::message_for_consumer::message::Message::SUBSCRIBE(subscribe) => {
+                    let Subscribe { topic_name, credits } = subscribe;
+                    let mut subscription = self.subscription.lock().await;
+                    // A new subscription replaces the previous one.
+                    subscription.take();
+                    let res = Subscription::start(
+                        Arc::clone(&self.topics_data),
+                        self.id.clone(),
+                        topic_name.clone(),
+                        credits,
+                        header,
+                        writer.clone(),
+                    ).await;
+                    match res {
+                        Ok(started) => *subscription = Some((topic_name, started)),
+                        Err(e) => Failure::new(&e).send_message(&mut writer, &header).await,
+                    }
+                }
+                crate::state::message_from_client::message_for_consumer::message::Message::CREDIT(credit) => {
+                    let Credit { topic_name, credits } = credit;
+                    let res = match self.subscription.lock().await.as_ref() {
+                        Some((subscribed_topic, subscription)) if *subscribed_topic == topic_name => subscription.add_credits(credits),
+                        _ => Err(BrokerError::invalid_request(format!("not subscribed to topic {}", topic_name))),
+                    };
+                    match res {
+                        Ok(()) => Success::new().send_message(&mut writer, &header).await,
+                        Err(e) => Failure::new(&e).send_message(&mut writer, &header).await,
+                    }
+                }
+                crate::state::message_from_client::message_for_consumer::message::Message::HEARTBEAT(heartbeat) => {
+                    let Heartbeat { topic_name } = heartbeat;
+                    let res;
+                    {
+                        let mut topic_guard = self.topics_data.write().await;
+                        res = topic_guard.heartbeat(&self.id, &topic_name);
+                    }
+                    match res {
+                        Ok(()) => Success::new().send_message(&mut writer, &header).await,
+                        Err(e) => Failure::new(&e).send_message(&mut writer, &header).await,
+                    }
+                }
+                crate::state::message_from_client::message_for_consumer::message::Message::REVOKEPARTITIONS(revoke_partitions) => {
+                    let res;
+                    {
+                        let mut topic_guard = self.topics_data.write().await;
+                        res = topic_guard.revoke_partitions(&self.id, &revoke_partitions);
+                    }
+                    match res {
+                        Ok(()) => Success::new().send_message(&mut writer, &header).await,
+                        Err(e) => Failure::new(&e).send_message(&mut writer, &header).await,
+                    }
+                }
+                crate::state::message_from_client::message_for_consumer::message::Message::DESCRIBEASSIGNMENT(describe_assignment) => {
+                    let DescribeAssignment { topic_name } = describe_assignment;
+                    let res;
+                    {
+                        let topic_guard = self.topics_data.read().await;
+                        res = topic_guard.describe_assignment(&self.id, &topic_name);
+                    }
+                    match res {
+                        Ok(assignment) => AssignmentMessage::new(topic_name, assignment).send_message(&mut writer, &header).await,
+                        Err(e) => Failure::new(&e).send_message(&mut writer, &header).await,
+                    }
+                }
+                crate::state::message_from_client::message_for_consumer::message::Message::POLL(poll) => {
+                    let response;
+                    {
+                        let mut topic_guard = self.topics_data.write().await;
+                        response = topic_guard.poll(&self.id, &poll).await;
+                    }
+                    match response {
+                        Ok(polled) => PollMessage::new(polled).send_message(&mut writer, &header).await,
+                        Err(e) => Failure::new(&e).send_message(&mut writer, &header).await,
+                    }
+                }
+        }
+    }
 }
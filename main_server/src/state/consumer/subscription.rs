@@ -0,0 +1,195 @@
+use std::{collections::HashMap, sync::Arc};
+
+use tokio::sync::{RwLock, Semaphore, oneshot};
+
+use crate::state::{
+    message_from_client::message_for_consumer::message::Fetch,
+    message_to_client::{
+        failure_message::Failure, offset_message::OffsetMessage, push_message::PushMessage,
+        success_message::Success,
+    },
+    protocol::{broker_error::BrokerError, connection::ResponseWriter, frame::RequestHeader},
+    topic_state::topic_state::Topic,
+};
+
+/// Most bytes of records pushed in a single message.
+const MAX_PUSH_BYTES: i32 = 1024 * 1024;
+
+/// Streams the records appended to the partitions assigned to a consumer down its
+/// connection, starting from where every partition was when the subscription
+/// started. A partition assigned later is streamed from after the group's
+/// committed offset, or from its start when nothing was committed. The consumer grants credits, one per record; pushing pauses when they
+/// run out and resumes once more are granted. Dropping the subscription stops it.
+pub struct Subscription {
+    credits: Arc<Semaphore>,
+    _stop: oneshot::Sender<()>,
+}
+
+impl Subscription {
+    /// Answers the `SUBSCRIBE` request `header` belongs to and starts pushing, so
+    /// that the answer goes out before the first records.
+    pub async fn start(
+        topics_data: Arc<RwLock<Topic>>,
+        consumer_id: String,
+        topic_name: String,
+        credits: i32,
+        header: RequestHeader,
+        mut writer: ResponseWriter,
+    ) -> Result<Self, BrokerError> {
+        if credits < 0 {
+            return Err(BrokerError::invalid_request("credits must not be negative"));
+        }
+        let positions;
+        {
+            let topic_guard = topics_data.read().await;
+            positions = topic_guard
+                .assigned_partitions(&consumer_id, &topic_name)?
+                .into_iter()
+                .map(|partition| {
+                    let high_watermark = topic_guard
+                        .messages_store
+                        .high_watermark(&partition, &topic_name);
+                    (partition, high_watermark)
+                })
+                .collect();
+        }
+        Success::new().send_message(&mut writer, &header).await;
+        let credits = Arc::new(Semaphore::new(credits as usize));
+        let (stop, stopped) = oneshot::channel();
+        tokio::spawn(push_records(
+            topics_data,
+            consumer_id,
+            topic_name,
+            positions,
+            Arc::clone(&credits),
+            header,
+            writer,
+            stopped,
+        ));
+        Ok(Self {
+            credits,
+            _stop: stop,
+        })
+    }
+
+    pub fn add_credits(&self, credits: i32) -> Result<(), BrokerError> {
+        if credits < 0 {
+            return Err(BrokerError::invalid_request("credits must not be negative"));
+        }
+        self.credits.add_permits(credits as usize);
+        Ok(())
+    }
+}
+
+/// Pushes records until `stopped` fires, going over the assigned partitions round
+/// robin so a busy partition does not starve the others. `positions` holds the next
+/// offset to push for every assigned partition.
+#[allow(clippy::too_many_arguments)]
+async fn push_records(
+    topics_data: Arc<RwLock<Topic>>,
+    consumer_id: String,
+    topic_name: String,
+    mut positions: HashMap<i32, i32>,
+    credits: Arc<Semaphore>,
+    header: RequestHeader,
+    mut writer: ResponseWriter,
+    mut stopped: oneshot::Receiver<()>,
+) {
+    let mut first_partition = 0;
+    loop {
+        tokio::select! {
+            _ = credits.acquire() => {}
+            _ = &mut stopped => return,
+        }
+        let assigned;
+        {
+            let topic_guard = topics_data.read().await;
+            assigned = topic_guard
+                .assigned_partitions(&consumer_id, &topic_name)
+                .and_then(|partitions| {
+                    let group_id = topic_guard.consumer_group(&consumer_id, &topic_name)?;
+                    positions.retain(|partition, _| partitions.contains(partition));
+                    for partition in &partitions {
+                        positions.entry(*partition).or_insert_with(|| {
+                            topic_guard.messages_store.read_position(
+                                partition,
+                                &topic_name,
+                                &group_id,
+                                None,
+                            )
+                        });
+                    }
+                    Ok((partitions, topic_guard.topic_new_data_notify(&topic_name)?))
+                });
+        }
+        let (partitions, new_data) = match assigned {
+            Ok(assigned) => assigned,
+            Err(e) => {
+                Failure::new(&e).send_message(&mut writer, &header).await;
+                return;
+            }
+        };
+        // Created before fetching, so an append right after a fetch still wakes
+        // the subscription up.
+        let notified = new_data.notified();
+        let mut pushed = false;
+        for i in 0..partitions.len() {
+            let partition = partitions[(first_partition + i) % partitions.len()];
+            let available = credits.available_permits();
+            if available == 0 {
+                break;
+            }
+            let fetch = Fetch {
+                topic_name: topic_name.clone(),
+                partition,
+                offset: positions[&partition],
+                max_records: available.min(i32::MAX as usize) as i32,
+                max_bytes: MAX_PUSH_BYTES,
+                min_bytes: 0,
+                decompress: false,
+                max_wait_ms: 0,
+            };
+            let fetched;
+            {
+                let topic_guard = topics_data.read().await;
+                fetched = topic_guard.fetch(&fetch).await;
+            }
+            let records = match fetched {
+                Ok(fetched) => fetched.records,
+                Err(BrokerError::OffsetOutOfRange {
+                    log_start_offset, ..
+                }) => {
+                    // Retention got there first; carry on from what is left.
+                    positions.insert(partition, log_start_offset);
+                    pushed = true;
+                    continue;
+                }
+                Err(e) => {
+                    Failure::new(&e).send_message(&mut writer, &header).await;
+                    return;
+                }
+            };
+            let Some(last_record) = records.last() else {
+                continue;
+            };
+            positions.insert(partition, last_record.last_offset() as i32 + 1);
+            credits
+                .acquire_many(records.len() as u32)
+                .await
+                .unwrap()
+                .forget();
+            let records = records.into_iter().map(OffsetMessage::new).collect();
+            PushMessage::new(topic_name.clone(), partition, records)
+                .send_message(&mut writer, &header)
+                .await;
+            pushed = true;
+        }
+        first_partition = first_partition.wrapping_add(1);
+        if !pushed {
+            tokio::select! {
+                _ = notified => {}
+                _ = &mut stopped => return,
+            }
+        }
+    }
+}
@@ -1,5 +1,8 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use uuid::Uuid;
 
+#[derive(Default)]
 pub struct Helper {}
 
 impl Helper {
@@ -12,3 +15,10 @@ impl Helper {
         uuid.to_string()
     }
 }
+
+pub fn current_time_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as i64)
+        .unwrap_or(0)
+}
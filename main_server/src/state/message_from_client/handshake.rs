@@ -0,0 +1,15 @@
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientType {
+    Producer,
+    Consumer,
+}
+
+/// First request on every connection, replacing the old `{"message": 0 | 1}`.
+/// The broker answers with the api versions it supports.
+#[derive(Deserialize)]
+pub struct Handshake {
+    pub client_type: ClientType,
+}
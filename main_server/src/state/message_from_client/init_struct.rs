@@ -1,6 +0,0 @@
-use serde::Deserialize;
-
-#[derive(Deserialize)]
-pub struct InitProducerConsumer {
-    pub message: i32,
-}
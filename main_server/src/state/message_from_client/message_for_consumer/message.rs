@@ -1,9 +1,9 @@
 use serde::Deserialize;
 
-#[derive(Deserialize)]
-pub struct ConsumerMessage {
-    pub message: Message,
-}
+use crate::state::topic_state::{
+    assignor::AssignmentStrategy,
+    consumer_group::{DEFAULT_GROUP_ID, NO_GENERATION},
+};
 
 #[derive(Deserialize)]
 pub enum Message {
@@ -11,11 +11,38 @@ pub enum Message {
     LEAVECONSUMER(LeaveConsumer),
     GETOFFSETMESSAGE(GetOffsetMessage),
     COMMITOFFSET(CommitOffset),
+    OFFSETFORTIMESTAMP(OffsetForTimestamp),
+    FETCH(Fetch),
+    SUBSCRIBE(Subscribe),
+    CREDIT(Credit),
+    POLL(Poll),
+    HEARTBEAT(Heartbeat),
+    REVOKEPARTITIONS(RevokePartitions),
+    DESCRIBEASSIGNMENT(DescribeAssignment),
 }
 
+/// Joins the consumer group `group_id` of the topic. Every group gets all of the
+/// topic's partitions, split between its members, and commits its own offsets.
+/// The timeouts and the assignment strategy apply to the group when this join
+/// creates it; a timeout of 0 takes the broker's default. Version 0 has no
+/// `group_id` and joins the default group; the timeouts came in version 2 and
+/// the assignment strategy in version 3. Consumers that join with an older
+/// version are only held to the session timeout once they send a `HEARTBEAT`.
 #[derive(Deserialize)]
 pub struct JoinConsumer {
     pub topic_name: String,
+    #[serde(default = "default_group_id")]
+    pub group_id: String,
+    #[serde(default)]
+    pub session_timeout_ms: i64,
+    #[serde(default)]
+    pub max_poll_interval_ms: i64,
+    #[serde(default)]
+    pub assignment_strategy: AssignmentStrategy,
+}
+
+fn default_group_id() -> String {
+    DEFAULT_GROUP_ID.to_string()
 }
 
 #[derive(Deserialize)]
@@ -28,11 +55,96 @@ pub struct GetOffsetMessage {
     pub topic_name: String,
     pub partition: i32,
     pub offset: i32,
+    /// Unpack compressed batches and return just the record at `offset`.
+    #[serde(default)]
+    pub decompress: bool,
 }
 
+/// Commits `offset` for the group the consumer joined the topic with. Rejected
+/// unless the consumer owns the partition in the group and `generation` is the
+/// group's current one. Version 0 has no `generation` and is not fenced by one.
 #[derive(Deserialize)]
 pub struct CommitOffset {
     pub topic_name: String,
     pub partition: i32,
     pub offset: i32,
+    #[serde(default = "no_generation")]
+    pub generation: i32,
+}
+
+fn no_generation() -> i32 {
+    NO_GENERATION
+}
+
+#[derive(Deserialize)]
+pub struct OffsetForTimestamp {
+    pub topic_name: String,
+    pub partition: i32,
+    pub timestamp: i64,
+}
+
+/// Reads up to `max_records` records or `max_bytes` bytes of records starting at
+/// `offset`. While fewer than `min_bytes` bytes are available the broker waits up
+/// to `max_wait_ms` for more records, then answers with whatever there is; version
+/// 0 has no `max_wait_ms` and answers right away.
+#[derive(Deserialize)]
+pub struct Fetch {
+    pub topic_name: String,
+    pub partition: i32,
+    pub offset: i32,
+    pub max_records: i32,
+    pub max_bytes: i32,
+    #[serde(default)]
+    pub min_bytes: i32,
+    /// Unpack compressed batches into their records.
+    #[serde(default)]
+    pub decompress: bool,
+    #[serde(default)]
+    pub max_wait_ms: i64,
+}
+
+/// Streams new records from the partitions of the topic assigned to the consumer,
+/// as long as the consumer has credits left; it starts with `credits`.
+#[derive(Deserialize)]
+pub struct Subscribe {
+    pub topic_name: String,
+    pub credits: i32,
+}
+
+/// Grants the subscription to the topic `credits` more records.
+#[derive(Deserialize)]
+pub struct Credit {
+    pub topic_name: String,
+    pub credits: i32,
+}
+
+/// Reads the next records from the partitions of the topic assigned to the
+/// consumer, up to `max_records` records or `max_bytes` bytes.
+#[derive(Deserialize)]
+pub struct Poll {
+    pub topic_name: String,
+    pub max_records: i32,
+    pub max_bytes: i32,
+}
+
+/// Tells the broker the consumer is alive; without one within the group's session
+/// timeout the consumer is evicted and has to join again.
+#[derive(Deserialize)]
+pub struct Heartbeat {
+    pub topic_name: String,
+}
+
+/// Gives up the partitions revoked from the consumer in `generation`, once it has
+/// stopped reading them and committed their offsets, so that their new owners can
+/// start.
+#[derive(Deserialize)]
+pub struct RevokePartitions {
+    pub topic_name: String,
+    pub generation: i32,
+}
+
+/// Asks which partitions of the topic the consumer owns in its group.
+#[derive(Deserialize)]
+pub struct DescribeAssignment {
+    pub topic_name: String,
 }
@@ -1,21 +1,22 @@
 use serde::Deserialize;
 
-#[derive(Deserialize)]
-pub struct ProducerMessage {
-    pub message: Message,
-}
+use crate::state::{config::topic_config::TopicConfig, message_state::compression::Codec};
 
 #[derive(Deserialize)]
 pub enum Message {
     CREATETOPIC(CreateTopic),
     DELETETOPIC(DeleteTopic),
     MESSAGETOPIC(MessageTopic),
+    MESSAGEBATCH(MessageBatch),
+    PRODUCEBATCH(ProduceBatch),
 }
 
 #[derive(Deserialize)]
 pub struct CreateTopic {
     pub topic_name: String,
     pub partitions: i32,
+    #[serde(default)]
+    pub config: TopicConfig,
 }
 
 #[derive(Deserialize)]
@@ -27,5 +28,51 @@ pub struct DeleteTopic {
 pub struct MessageTopic {
     pub key: Option<String>,
     pub topic_name: String,
-    pub data: Vec<u8>,
+    /// `null` produces a tombstone, deleting `key` from a compacted topic.
+    pub data: Option<Vec<u8>>,
+    /// Milliseconds since the epoch; the broker stamps the record when absent.
+    #[serde(default)]
+    pub timestamp: Option<i64>,
+}
+
+/// `record_count` records compressed together with `codec`. Decompressed, `records`
+/// holds `key_length: i32 | key | value_length: i32 | value` for every record,
+/// big endian, with a length of `-1` for a missing key or a tombstone.
+#[derive(Deserialize)]
+pub struct MessageBatch {
+    pub key: Option<String>,
+    pub topic_name: String,
+    pub codec: Codec,
+    pub record_count: i32,
+    pub records: Vec<u8>,
+    #[serde(default)]
+    pub timestamp: Option<i64>,
+}
+
+/// Records for any number of topics and partitions in one request. The records of
+/// each partition are appended together or not at all.
+#[derive(Deserialize)]
+pub struct ProduceBatch {
+    pub topics: Vec<TopicRecords>,
+}
+
+#[derive(Deserialize)]
+pub struct TopicRecords {
+    pub topic_name: String,
+    pub partitions: Vec<PartitionRecords>,
+}
+
+#[derive(Deserialize)]
+pub struct PartitionRecords {
+    pub partition: i32,
+    pub records: Vec<ProduceRecord>,
+}
+
+#[derive(Deserialize)]
+pub struct ProduceRecord {
+    pub key: Option<String>,
+    /// `null` produces a tombstone.
+    pub data: Option<Vec<u8>>,
+    #[serde(default)]
+    pub timestamp: Option<i64>,
 }
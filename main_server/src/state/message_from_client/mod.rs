@@ -1,3 +1,3 @@
-pub mod init_struct;
+pub mod handshake;
 pub mod message_for_consumer;
 pub mod message_for_producer;
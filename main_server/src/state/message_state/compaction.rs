@@ -0,0 +1,236 @@
+use std::{collections::HashMap, io};
+
+use crate::state::{
+    helpers::helper::current_time_millis,
+    message_state::{
+        record::Record,
+        segment::{Segment, SegmentReader},
+    },
+};
+
+/// The segments of a compacted partition, copied out of the store so that they
+/// can be cleaned without holding its lock.
+pub struct CompactionJob {
+    pub topic_name: String,
+    pub partition: i32,
+    /// Every segment of the partition, the active one last.
+    pub segments: Vec<Segment>,
+    pub delete_retention_ms: i64,
+    pub index_interval_bytes: u64,
+}
+
+/// What a `CompactionJob` did, to be swapped into the store.
+pub struct CompactedPartition {
+    pub topic_name: String,
+    pub partition: i32,
+    /// Base offset of the active segment when the job was taken; every closed
+    /// segment below it has been cleaned.
+    pub active_base_offset: i32,
+    /// Every closed segment that lost records, with what is left of it, `None`
+    /// when nothing is.
+    pub cleaned: Vec<(Segment, Option<Segment>)>,
+    /// When the first of the tombstones that were kept is due to be dropped.
+    pub tombstones_expire_at: Option<i64>,
+}
+
+impl CompactionJob {
+    /// Finds the latest record of every key and writes a cleaned copy of each
+    /// closed segment holding records that are not, or tombstones older than
+    /// `delete_retention_ms`. Records without a key and compressed batches are never
+    /// removed. Blocks on file I/O.
+    pub fn run(self) -> io::Result<CompactedPartition> {
+        let now = current_time_millis();
+        let (active, closed) = self.segments.split_last().unwrap();
+        // Offset, timestamp and whether it is a tombstone, of the latest record of
+        // every key. The active segment is only read up to where it ended when the
+        // job was taken.
+        let mut latest_records: HashMap<Vec<u8>, (i64, i64, bool)> = HashMap::new();
+        let readers = closed
+            .iter()
+            .map(|segment| SegmentReader::open(&segment.path))
+            .chain([SegmentReader::open_until(&active.path, active.size)]);
+        for reader in readers {
+            let mut reader = reader?;
+            while let Some(record) = reader.next_record()? {
+                if let Some(key) = record.key {
+                    latest_records.insert(
+                        key,
+                        (record.offset, record.timestamp, record.value.is_none()),
+                    );
+                }
+            }
+        }
+        let delete_horizon = now - self.delete_retention_ms;
+        let keep = |record: &Record| match &record.key {
+            None => true,
+            Some(key) => {
+                latest_records
+                    .get(key)
+                    .is_some_and(|(offset, ..)| *offset == record.offset)
+                    && (record.value.is_some() || record.timestamp >= delete_horizon)
+            }
+        };
+        let mut cleaned = Vec::new();
+        for segment in closed {
+            let mut reader = SegmentReader::open(&segment.path)?;
+            let mut removes_records = false;
+            while let Some(record) = reader.next_record()? {
+                if !keep(&record) {
+                    removes_records = true;
+                    break;
+                }
+            }
+            if removes_records {
+                let cleaned_segment = segment.write_cleaned(keep, self.index_interval_bytes)?;
+                cleaned.push((segment.clone(), cleaned_segment));
+            }
+        }
+        let tombstones_expire_at = latest_records
+            .values()
+            .filter(|(offset, timestamp, tombstone)| {
+                *tombstone && *timestamp >= delete_horizon && *offset < active.base_offset as i64
+            })
+            .map(|(_, timestamp, _)| timestamp + self.delete_retention_ms)
+            .min();
+        Ok(CompactedPartition {
+            topic_name: self.topic_name,
+            partition: self.partition,
+            active_base_offset: active.base_offset,
+            cleaned,
+            tombstones_expire_at,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    use super::*;
+    use crate::state::message_state::segment::{
+        CLEANED_EXTENSION, SegmentWriter, segment_file_name,
+    };
+
+    const DELETE_RETENTION_MS: i64 = 60_000;
+
+    /// Directory for one test's segment files, emptied first.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("compaction-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn record(offset: i64, timestamp: i64, key: Option<&str>, value: Option<&str>) -> Record {
+        Record::new(
+            offset,
+            timestamp,
+            key.map(|key| key.as_bytes().to_vec()),
+            value.map(|value| value.as_bytes().to_vec()),
+        )
+    }
+
+    /// Writes a segment holding `records`, which start at its base offset.
+    fn segment(dir: &Path, records: &[Record]) -> Segment {
+        let base_offset = records[0].offset as i32;
+        let path = dir.join(segment_file_name(base_offset));
+        SegmentWriter::open(&path).unwrap().append(records).unwrap();
+        Segment::recover(path, base_offset, 0, 4096, false).unwrap()
+    }
+
+    fn job(segments: Vec<Segment>) -> CompactionJob {
+        CompactionJob {
+            topic_name: "t".to_string(),
+            partition: 0,
+            segments,
+            delete_retention_ms: DELETE_RETENTION_MS,
+            index_interval_bytes: 4096,
+        }
+    }
+
+    fn cleaned_offsets(segment: &Segment) -> Vec<i64> {
+        let mut reader =
+            SegmentReader::open(segment.path.with_extension(CLEANED_EXTENSION)).unwrap();
+        let mut offsets = Vec::new();
+        while let Some(record) = reader.next_record().unwrap() {
+            offsets.push(record.offset);
+        }
+        offsets
+    }
+
+    #[test]
+    fn keeps_latest_record_per_key() {
+        let dir = test_dir("latest");
+        let now = current_time_millis();
+        let segments = vec![
+            segment(
+                &dir,
+                &[
+                    record(0, now, Some("a"), Some("1")),
+                    record(1, now, Some("b"), Some("1")),
+                    record(2, now, Some("a"), Some("2")),
+                    record(3, now, None, Some("no key")),
+                ],
+            ),
+            segment(&dir, &[record(4, now, Some("b"), Some("2"))]),
+            // The latest "a" is in the active segment, which is never cleaned.
+            segment(&dir, &[record(5, now, Some("a"), Some("3"))]),
+        ];
+
+        let compacted = job(segments).run().unwrap();
+        assert_eq!(compacted.active_base_offset, 5);
+        assert_eq!(compacted.cleaned.len(), 1);
+        let (original, cleaned) = &compacted.cleaned[0];
+        assert_eq!(original.base_offset, 0);
+        let cleaned = cleaned.as_ref().unwrap();
+        assert_eq!(cleaned.base_offset, 0);
+        assert_eq!(cleaned.next_offset, 4);
+        assert_eq!(cleaned_offsets(cleaned), vec![3]);
+        assert_eq!(compacted.tombstones_expire_at, None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn drops_tombstones_after_grace_period() {
+        let dir = test_dir("tombstones");
+        let now = current_time_millis();
+        let expired = now - 2 * DELETE_RETENTION_MS;
+        let segments = vec![
+            segment(
+                &dir,
+                &[
+                    record(0, expired, Some("a"), Some("1")),
+                    record(1, expired, Some("a"), None),
+                ],
+            ),
+            segment(
+                &dir,
+                &[
+                    record(2, now, Some("b"), Some("1")),
+                    record(3, now, Some("b"), None),
+                ],
+            ),
+            segment(&dir, &[record(4, now, Some("c"), Some("1"))]),
+        ];
+
+        let compacted = job(segments).run().unwrap();
+        assert_eq!(compacted.cleaned.len(), 2);
+        // Nothing is left of the segment whose only key was deleted long ago.
+        let (original, cleaned) = &compacted.cleaned[0];
+        assert_eq!(original.base_offset, 0);
+        assert!(cleaned.is_none());
+        assert!(!original.path.with_extension(CLEANED_EXTENSION).exists());
+        // The recent tombstone stays until its grace period is over.
+        let (original, cleaned) = &compacted.cleaned[1];
+        assert_eq!(original.base_offset, 2);
+        assert_eq!(cleaned_offsets(cleaned.as_ref().unwrap()), vec![3]);
+        assert_eq!(
+            compacted.tombstones_expire_at,
+            Some(now + DELETE_RETENTION_MS)
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
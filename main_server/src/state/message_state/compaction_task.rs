@@ -0,0 +1,51 @@
+use std::{fs, io::ErrorKind, sync::Arc, time::Duration};
+
+use tokio::sync::RwLock;
+
+use crate::state::topic_state::topic_state::Topic;
+
+/// Background loop compacting the closed segments of topics with
+/// `cleanup_policy` set to `compact`, checked every `interval`. Segments are read
+/// and rewritten off the store's lock, which is only taken to find the partitions
+/// to clean and to swap the cleaned segments in.
+pub async fn run_compaction(topics_data: Arc<RwLock<Topic>>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let jobs = topics_data.read().await.messages_store.compaction_jobs();
+        for job in jobs {
+            let compacted = match tokio::task::spawn_blocking(move || job.run()).await {
+                Ok(Ok(compacted)) => compacted,
+                Ok(Err(e)) => {
+                    eprintln!("Failed to compact topics; err = {:?}", e);
+                    continue;
+                }
+                Err(e) => {
+                    eprintln!("Compaction task failed; err = {:?}", e);
+                    continue;
+                }
+            };
+            let res = topics_data
+                .write()
+                .await
+                .messages_store
+                .apply_compaction(compacted);
+            match res {
+                Ok(obsolete_files) => {
+                    let _ = tokio::task::spawn_blocking(move || {
+                        for path in obsolete_files {
+                            match fs::remove_file(&path) {
+                                Err(e) if e.kind() != ErrorKind::NotFound => {
+                                    eprintln!("Failed to delete {:?}; err = {:?}", path, e);
+                                }
+                                _ => {}
+                            }
+                        }
+                    })
+                    .await;
+                }
+                Err(e) => eprintln!("Failed to compact topics; err = {:?}", e),
+            }
+        }
+    }
+}
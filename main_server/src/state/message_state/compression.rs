@@ -0,0 +1,108 @@
+use std::io::{self, ErrorKind, Read};
+
+use serde::{Deserialize, Serialize};
+
+/// Compression codec of a record batch, kept in the low bits of a record's
+/// attributes byte. The compressed formats are the standard framed ones: gzip,
+/// the LZ4 frame format, zstd frames and the snappy framing format.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    #[default]
+    None,
+    Gzip,
+    Lz4,
+    Zstd,
+    Snappy,
+}
+
+const CODEC_MASK: u8 = 0b111;
+
+impl Codec {
+    pub fn from_attributes(attributes: u8) -> io::Result<Self> {
+        match attributes & CODEC_MASK {
+            0 => Ok(Codec::None),
+            1 => Ok(Codec::Gzip),
+            2 => Ok(Codec::Lz4),
+            3 => Ok(Codec::Zstd),
+            4 => Ok(Codec::Snappy),
+            _ => Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "unknown compression codec",
+            )),
+        }
+    }
+
+    pub fn attributes(&self) -> u8 {
+        match self {
+            Codec::None => 0,
+            Codec::Gzip => 1,
+            Codec::Lz4 => 2,
+            Codec::Zstd => 3,
+            Codec::Snappy => 4,
+        }
+    }
+
+    /// Decompresses `data`, failing once the output grows past `limit` bytes.
+    /// Decompression failures are reported as `InvalidData`, like any other
+    /// malformed record.
+    pub fn decompress(&self, data: &[u8], limit: usize) -> io::Result<Vec<u8>> {
+        let mut decompressed = Vec::new();
+        // One byte over the limit is enough to tell the output does not fit.
+        let take = limit as u64 + 1;
+        let res = match self {
+            Codec::None => data.take(take).read_to_end(&mut decompressed),
+            Codec::Gzip => flate2::read::GzDecoder::new(data)
+                .take(take)
+                .read_to_end(&mut decompressed),
+            Codec::Lz4 => lz4_flex::frame::FrameDecoder::new(data)
+                .take(take)
+                .read_to_end(&mut decompressed),
+            Codec::Zstd => zstd::stream::read::Decoder::new(data)
+                .and_then(|decoder| decoder.take(take).read_to_end(&mut decompressed)),
+            Codec::Snappy => snap::read::FrameDecoder::new(data)
+                .take(take)
+                .read_to_end(&mut decompressed),
+        };
+        match res {
+            Ok(_) if decompressed.len() > limit => Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("{:?} batch decompresses to more than {} bytes", self, limit),
+            )),
+            Ok(_) => Ok(decompressed),
+            Err(e) => Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("failed to decompress {:?} batch: {}", self, e),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn decompresses_up_to_the_limit() {
+        let data = vec![7u8; 1000];
+        assert_eq!(Codec::Gzip.decompress(&gzip(&data), 1000).unwrap(), data);
+        assert_eq!(Codec::None.decompress(&data, 1000).unwrap(), data);
+    }
+
+    #[test]
+    fn rejects_output_past_the_limit() {
+        let bomb = gzip(&vec![0u8; 1 << 20]);
+        let error = Codec::Gzip.decompress(&bomb, 1000).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        let error = Codec::None.decompress(&[0; 1001], 1000).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
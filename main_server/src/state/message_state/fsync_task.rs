@@ -0,0 +1,28 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::sync::RwLock;
+
+use crate::state::topic_state::topic_state::Topic;
+
+/// Background loop for `FsyncPolicy::Interval`: every `interval` the segments that
+/// received appends since the previous pass are synced to disk, off the store's
+/// lock.
+pub async fn run_interval_fsync(topics_data: Arc<RwLock<Topic>>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let syncs = topics_data.write().await.messages_store.take_syncs();
+        let syncs = match syncs {
+            Ok(syncs) => syncs,
+            Err(e) => {
+                eprintln!("Failed to fsync active segments; err = {:?}", e);
+                continue;
+            }
+        };
+        for sync in syncs {
+            if let Err(e) = sync.wait().await {
+                eprintln!("Failed to fsync active segments; err = {:?}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use crate::state::message_state::segment::{Segment, SegmentReader, parse_segment_file_name};
+
+/// Where the first bad record of a partition was found.
+struct Corruption {
+    segment: Segment,
+    position: u64,
+    reason: String,
+}
+
+/// Checks every record of every partition of `topic_name` under `logs/`, reporting
+/// the first corrupted, torn or out of order record of each partition. With
+/// `truncate` the partition is cut back to its last valid record: the bad segment
+/// is truncated in front of it, later segments are deleted and the indexes of the
+/// truncated segment are removed so that the broker rebuilds them on its next start.
+///
+/// The broker must not be running on the same directory. Returns how many
+/// partitions are left corrupted.
+pub fn verify_topic(topic_name: &str, truncate: bool) -> io::Result<usize> {
+    let topic_path = env::current_dir()?.join("logs").join(topic_name);
+    let mut partitions = Vec::new();
+    for entry in fs::read_dir(&topic_path)? {
+        let entry = entry?;
+        let partition = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse::<i32>().ok());
+        if let Some(partition) = partition
+            && entry.file_type()?.is_dir()
+        {
+            partitions.push(partition);
+        }
+    }
+    partitions.sort();
+
+    let mut corrupted_partitions = 0;
+    for partition in partitions {
+        let partition_path = topic_path.join(format!("{}", partition));
+        let segments = segment_paths(&partition_path)?;
+        let (record_count, last_offset, corruption) = verify_segments(&segments)?;
+        let last_offset = last_offset.map_or("none".to_string(), |offset| offset.to_string());
+        let Some(corruption) = corruption else {
+            println!(
+                "{}/{}: ok, {} records, last offset {}",
+                topic_name, partition, record_count, last_offset
+            );
+            continue;
+        };
+        println!(
+            "{}/{}: {} at byte {} of {:?}, {} valid records before it, last valid offset {}",
+            topic_name,
+            partition,
+            corruption.reason,
+            corruption.position,
+            corruption.segment.path,
+            record_count,
+            last_offset
+        );
+        if !truncate {
+            corrupted_partitions += 1;
+            continue;
+        }
+        truncate_partition(&segments, &corruption)?;
+        println!(
+            "{}/{}: truncated back to offset {}",
+            topic_name, partition, last_offset
+        );
+    }
+    Ok(corrupted_partitions)
+}
+
+/// The segments of a partition ordered by base offset.
+fn segment_paths(partition_path: &Path) -> io::Result<Vec<(i32, PathBuf)>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(partition_path)? {
+        let path = entry?.path();
+        if let Some(base_offset) = parse_segment_file_name(&path) {
+            segments.push((base_offset, path));
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+/// Number of valid records, the offset of the last one and the first corruption.
+fn verify_segments(
+    segments: &[(i32, PathBuf)],
+) -> io::Result<(u64, Option<i64>, Option<Corruption>)> {
+    let mut record_count = 0;
+    let mut last_offset: Option<i64> = None;
+    for (base_offset, path) in segments {
+        let segment = Segment::new(path.clone(), *base_offset, 0);
+        let mut reader = SegmentReader::open(path)?;
+        let mut position = 0;
+        loop {
+            let reason = match reader.next_record() {
+                Ok(None) => break,
+                Ok(Some(record))
+                    if record.offset < *base_offset as i64
+                        || last_offset.is_some_and(|last_offset| record.offset <= last_offset) =>
+                {
+                    format!("record with out of order offset {}", record.offset)
+                }
+                Ok(Some(record)) => {
+                    position += record.encoded_len() as u64;
+                    record_count += record.record_count() as u64;
+                    last_offset = Some(record.last_offset());
+                    continue;
+                }
+                Err(e) if e.kind() == ErrorKind::InvalidData => e.to_string(),
+                Err(e) => return Err(e),
+            };
+            let corruption = Corruption {
+                segment,
+                position,
+                reason,
+            };
+            return Ok((record_count, last_offset, Some(corruption)));
+        }
+    }
+    Ok((record_count, last_offset, None))
+}
+
+fn truncate_partition(segments: &[(i32, PathBuf)], corruption: &Corruption) -> io::Result<()> {
+    let file = OpenOptions::new()
+        .write(true)
+        .open(&corruption.segment.path)?;
+    file.set_len(corruption.position)?;
+    file.sync_all()?;
+    for path in [
+        corruption.segment.index_path(),
+        corruption.segment.time_index_path(),
+    ] {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    for (base_offset, path) in segments {
+        if *base_offset > corruption.segment.base_offset {
+            Segment::new(path.clone(), *base_offset, 0).delete_files()?;
+        }
+    }
+    Ok(())
+}
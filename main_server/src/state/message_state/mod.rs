@@ -1 +1,12 @@
+pub mod compaction;
+pub mod compaction_task;
+pub mod compression;
+pub mod fsync_task;
+pub mod log_verifier;
+pub mod offset_index;
+pub mod record;
+pub mod retention_task;
+pub mod segment;
 pub mod store;
+pub mod store_error;
+pub mod time_index;
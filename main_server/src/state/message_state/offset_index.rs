@@ -0,0 +1,64 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+pub const OFFSET_INDEX_ENTRY_SIZE: usize = 8;
+
+/// One entry of a segment's sparse `.index` file: the record at
+/// `base_offset + relative_offset` starts `position` bytes into the `.log` file.
+/// Entries are stored back to back as two big endian u32s.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OffsetIndexEntry {
+    pub relative_offset: u32,
+    pub position: u32,
+}
+
+impl OffsetIndexEntry {
+    pub fn encode(&self) -> [u8; OFFSET_INDEX_ENTRY_SIZE] {
+        let mut buffer = [0u8; OFFSET_INDEX_ENTRY_SIZE];
+        buffer[..4].copy_from_slice(&self.relative_offset.to_be_bytes());
+        buffer[4..].copy_from_slice(&self.position.to_be_bytes());
+        buffer
+    }
+}
+
+/// Reads an index file, `Ok(None)` if it does not exist or ends in a partial entry
+/// left by a crash, in which case it has to be rebuilt from the segment.
+pub fn read_offset_index<P: AsRef<Path>>(path: P) -> io::Result<Option<Vec<OffsetIndexEntry>>> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if bytes.len() % OFFSET_INDEX_ENTRY_SIZE != 0 {
+        return Ok(None);
+    }
+    let entries = bytes
+        .chunks_exact(OFFSET_INDEX_ENTRY_SIZE)
+        .map(|chunk| OffsetIndexEntry {
+            relative_offset: u32::from_be_bytes(chunk[..4].try_into().unwrap()),
+            position: u32::from_be_bytes(chunk[4..].try_into().unwrap()),
+        })
+        .collect();
+    Ok(Some(entries))
+}
+
+pub fn write_offset_index<P: AsRef<Path>>(path: P, entries: &[OffsetIndexEntry]) -> io::Result<()> {
+    let mut bytes = Vec::with_capacity(entries.len() * OFFSET_INDEX_ENTRY_SIZE);
+    for entry in entries {
+        bytes.extend_from_slice(&entry.encode());
+    }
+    fs::write(path, bytes)
+}
+
+/// Byte position to start scanning from to find `relative_offset`: the position of
+/// the last indexed record at or before it, or the start of the segment.
+pub fn lookup_position(entries: &[OffsetIndexEntry], relative_offset: u32) -> u64 {
+    let index = entries.partition_point(|entry| entry.relative_offset <= relative_offset);
+    if index == 0 {
+        return 0;
+    }
+    entries[index - 1].position as u64
+}
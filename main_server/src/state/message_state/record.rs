@@ -0,0 +1,383 @@
+use std::io::{self, ErrorKind, Read};
+
+use crate::state::{message_state::compression::Codec, protocol::frame::MAX_FRAME_SIZE};
+
+/// Current on-disk record format version. Version 1 checksums records with
+/// CRC32C; version 0 records, checksummed with plain CRC32, are still readable.
+pub const MAGIC: u8 = 1;
+
+/// Bytes taken by the fixed part of a record after the length prefix:
+/// magic, attributes, offset, timestamp, key length, value length and crc.
+const FIXED_BODY_SIZE: usize = 1 + 1 + 8 + 8 + 4 + 4 + 4;
+
+/// Largest record length accepted when reading; a record is never bigger than the
+/// request frame that carried it.
+pub const MAX_RECORD_SIZE: usize = MAX_FRAME_SIZE;
+
+/// A single message as it is stored in a partition segment.
+///
+/// Layout (big endian):
+/// `length: u32 | magic: u8 | attributes: u8 | offset: i64 | timestamp: i64 |
+/// key_length: i32 | key | value_length: i32 | value | crc: u32`
+///
+/// `length` counts every byte after itself, a key length of `-1` means the record
+/// has no key, a value length of `-1` marks a tombstone (a null value deleting the
+/// key from compacted topics), and the crc covers everything from `magic` up to the
+/// end of `value`.
+///
+/// A record whose attributes name a compression codec is a batch: it has no key,
+/// its value is `record_count: i32` followed by the compressed entries, and it
+/// covers the offsets `offset..offset + record_count`. Decompressed, the entries
+/// are `key_length: i32 | key | value_length: i32 | value` back to back and share
+/// the batch's timestamp.
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub offset: i64,
+    pub timestamp: i64,
+    pub codec: Codec,
+    pub key: Option<Vec<u8>>,
+    pub value: Option<Vec<u8>>,
+}
+
+impl Record {
+    pub fn new(offset: i64, timestamp: i64, key: Option<Vec<u8>>, value: Option<Vec<u8>>) -> Self {
+        Self {
+            offset,
+            timestamp,
+            codec: Codec::None,
+            key,
+            value,
+        }
+    }
+
+    /// A batch of `record_count` entries compressed with `codec` as a producer sent them.
+    pub fn batch(
+        offset: i64,
+        timestamp: i64,
+        codec: Codec,
+        record_count: i32,
+        compressed: &[u8],
+    ) -> Self {
+        let mut value = Vec::with_capacity(4 + compressed.len());
+        value.extend_from_slice(&record_count.to_be_bytes());
+        value.extend_from_slice(compressed);
+        Self {
+            offset,
+            timestamp,
+            codec,
+            key: None,
+            value: Some(value),
+        }
+    }
+
+    /// Number of offsets the record takes up, more than one for a batch.
+    pub fn record_count(&self) -> i32 {
+        match (&self.codec, &self.value) {
+            (Codec::None, _) | (_, None) => 1,
+            (_, Some(value)) => i32::from_be_bytes(value[..4].try_into().unwrap()),
+        }
+    }
+
+    pub fn last_offset(&self) -> i64 {
+        self.offset + self.record_count() as i64 - 1
+    }
+
+    /// The compressed entries of a batch.
+    pub fn compressed_entries(&self) -> &[u8] {
+        &self.value.as_ref().unwrap()[4..]
+    }
+
+    /// The individual records of a batch, decompressed; a plain record is returned
+    /// as is. A batch is rejected when its entries decompress to more than the
+    /// largest record could hold.
+    pub fn unpack(&self) -> io::Result<Vec<Record>> {
+        if self.codec == Codec::None {
+            return Ok(vec![self.clone()]);
+        }
+        let entries = self
+            .codec
+            .decompress(self.compressed_entries(), MAX_RECORD_SIZE)?;
+        let mut cursor = Cursor::new(&entries);
+        let mut records = Vec::new();
+        while !cursor.data.is_empty() {
+            let key = cursor.read_bytes()?;
+            let value = cursor.read_bytes()?;
+            let offset = self.offset + records.len() as i64;
+            records.push(Record::new(offset, self.timestamp, key, value));
+        }
+        if records.len() as i32 != self.record_count() {
+            return Err(invalid_data(
+                "batch record count does not match its entries",
+            ));
+        }
+        Ok(records)
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        4 + FIXED_BODY_SIZE
+            + self.key.as_ref().map_or(0, |key| key.len())
+            + self.value.as_ref().map_or(0, |value| value.len())
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(self.encoded_len());
+        buffer.extend_from_slice(&((self.encoded_len() - 4) as u32).to_be_bytes());
+        buffer.push(MAGIC);
+        buffer.push(self.codec.attributes());
+        buffer.extend_from_slice(&self.offset.to_be_bytes());
+        buffer.extend_from_slice(&self.timestamp.to_be_bytes());
+        write_bytes(&mut buffer, self.key.as_deref());
+        write_bytes(&mut buffer, self.value.as_deref());
+        let crc = crc32c::crc32c(&buffer[4..]);
+        buffer.extend_from_slice(&crc.to_be_bytes());
+        buffer
+    }
+
+    /// Reads the next record from `reader`, which has `remaining` bytes left.
+    /// Returns `Ok(None)` on a clean end of file and an `InvalidData` error for
+    /// truncated or corrupted records.
+    pub fn read_from<R: Read>(reader: &mut R, remaining: u64) -> io::Result<Option<Self>> {
+        if remaining == 0 {
+            return Ok(None);
+        }
+        let mut length = [0u8; 4];
+        match reader.read_exact(&mut length) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let length = u32::from_be_bytes(length) as usize;
+        if length < FIXED_BODY_SIZE {
+            return Err(invalid_data("record length is smaller than its header"));
+        }
+        if length > MAX_RECORD_SIZE {
+            return Err(invalid_data("record length is larger than any record"));
+        }
+        if 4 + length as u64 > remaining {
+            return Err(invalid_data("record is truncated"));
+        }
+        let mut body = vec![0u8; length];
+        reader.read_exact(&mut body).map_err(|e| match e.kind() {
+            ErrorKind::UnexpectedEof => invalid_data("record is truncated"),
+            _ => e,
+        })?;
+        Self::decode(&body).map(Some)
+    }
+
+    fn decode(body: &[u8]) -> io::Result<Self> {
+        let (content, crc) = body.split_at(body.len() - 4);
+        let mut cursor = Cursor::new(content);
+        let expected_crc = match cursor.read_u8()? {
+            0 => crc32fast::hash(content),
+            MAGIC => crc32c::crc32c(content),
+            _ => return Err(invalid_data("unsupported record format version")),
+        };
+        if expected_crc != u32::from_be_bytes(crc.try_into().unwrap()) {
+            return Err(invalid_data("record crc mismatch"));
+        }
+        let codec = Codec::from_attributes(cursor.read_u8()?)?;
+        let offset = i64::from_be_bytes(cursor.read_array()?);
+        let timestamp = i64::from_be_bytes(cursor.read_array()?);
+        let key = cursor.read_bytes()?;
+        let value = cursor.read_bytes()?;
+        if codec != Codec::None {
+            let record_count = value
+                .as_ref()
+                .filter(|value| value.len() >= 4)
+                .map(|value| i32::from_be_bytes(value[..4].try_into().unwrap()));
+            if record_count.is_none_or(|record_count| record_count < 1) {
+                return Err(invalid_data("batch without a record count"));
+            }
+        }
+        Ok(Self {
+            offset,
+            timestamp,
+            codec,
+            key,
+            value,
+        })
+    }
+}
+
+struct Cursor<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Cursor<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() < n {
+            return Err(invalid_data("record field runs past the end of the record"));
+        }
+        let (head, tail) = self.data.split_at(n);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn read_u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn read_array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn read_bytes(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let length = i32::from_be_bytes(self.read_array()?);
+        if length < 0 {
+            return Ok(None);
+        }
+        Ok(Some(self.take(length as usize)?.to_vec()))
+    }
+}
+
+fn write_bytes(buffer: &mut Vec<u8>, bytes: Option<&[u8]>) {
+    match bytes {
+        Some(bytes) => {
+            buffer.extend_from_slice(&(bytes.len() as i32).to_be_bytes());
+            buffer.extend_from_slice(bytes);
+        }
+        None => buffer.extend_from_slice(&(-1i32).to_be_bytes()),
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(bytes: &[u8]) -> io::Result<Option<Record>> {
+        Record::read_from(&mut &bytes[..], bytes.len() as u64)
+    }
+
+    fn assert_same(record: &Record, decoded: &Record) {
+        assert_eq!(decoded.offset, record.offset);
+        assert_eq!(decoded.timestamp, record.timestamp);
+        assert_eq!(decoded.codec, record.codec);
+        assert_eq!(decoded.key, record.key);
+        assert_eq!(decoded.value, record.value);
+    }
+
+    #[test]
+    fn round_trips_records() {
+        let records = [
+            Record::new(0, 1_700_000_000_000, None, Some(b"value".to_vec())),
+            Record::new(7, -1, Some(b"key".to_vec()), Some(Vec::new())),
+            Record::new(i32::MAX as i64, 0, Some(b"key".to_vec()), None),
+        ];
+        for record in &records {
+            let encoded = record.encode();
+            assert_eq!(encoded.len(), record.encoded_len());
+            assert_same(record, &read(&encoded).unwrap().unwrap());
+        }
+    }
+
+    #[test]
+    fn reads_records_back_to_back() {
+        let first = Record::new(0, 10, None, Some(b"a".to_vec()));
+        let second = Record::new(1, 20, Some(b"k".to_vec()), Some(b"b".to_vec()));
+        let mut encoded = first.encode();
+        encoded.extend_from_slice(&second.encode());
+        let mut reader = &encoded[..];
+        let mut remaining = encoded.len() as u64;
+        for record in [&first, &second] {
+            let decoded = Record::read_from(&mut reader, remaining).unwrap().unwrap();
+            remaining -= decoded.encoded_len() as u64;
+            assert_same(record, &decoded);
+        }
+        assert!(Record::read_from(&mut reader, remaining).unwrap().is_none());
+    }
+
+    #[test]
+    fn returns_none_at_the_end() {
+        assert!(read(&[]).unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_truncated_records() {
+        let encoded = Record::new(3, 4, None, Some(b"value".to_vec())).encode();
+        let error = read(&encoded[..encoded.len() - 1]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_lengths_past_the_maximum_record_size() {
+        let mut encoded = ((MAX_RECORD_SIZE + 1) as u32).to_be_bytes().to_vec();
+        encoded.extend_from_slice(&[0; FIXED_BODY_SIZE]);
+        let error = read(&encoded).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_lengths_shorter_than_the_header() {
+        let mut encoded = ((FIXED_BODY_SIZE - 1) as u32).to_be_bytes().to_vec();
+        encoded.extend_from_slice(&[0; FIXED_BODY_SIZE - 1]);
+        let error = read(&encoded).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn round_trips_batches() {
+        let batch = Record::batch(5, 100, Codec::Gzip, 3, b"compressed");
+        let decoded = read(&batch.encode()).unwrap().unwrap();
+        assert_same(&batch, &decoded);
+        assert_eq!(decoded.record_count(), 3);
+        assert_eq!(decoded.last_offset(), 7);
+        assert_eq!(decoded.compressed_entries(), b"compressed");
+    }
+
+    /// Re-encodes `record` with format version `magic`, checksummed with `crc`.
+    fn encode_with(record: &Record, magic: u8, crc: fn(&[u8]) -> u32) -> Vec<u8> {
+        let mut encoded = record.encode();
+        let crc_start = encoded.len() - 4;
+        encoded[4] = magic;
+        let checksum = crc(&encoded[4..crc_start]);
+        encoded[crc_start..].copy_from_slice(&checksum.to_be_bytes());
+        encoded
+    }
+
+    #[test]
+    fn checksums_with_crc32c() {
+        let encoded = Record::new(1, 2, None, Some(b"value".to_vec())).encode();
+        let crc_start = encoded.len() - 4;
+        assert_eq!(encoded[4], MAGIC);
+        assert_eq!(
+            encoded[crc_start..],
+            crc32c::crc32c(&encoded[4..crc_start]).to_be_bytes()
+        );
+    }
+
+    #[test]
+    fn rejects_crc_mismatches() {
+        let record = Record::new(1, 2, Some(b"key".to_vec()), Some(b"value".to_vec()));
+        let encoded = record.encode();
+        for position in 4..encoded.len() {
+            let mut corrupted = encoded.clone();
+            corrupted[position] ^= 0x01;
+            let error = read(&corrupted).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData, "byte {position}");
+        }
+    }
+
+    #[test]
+    fn reads_version_0_records_checksummed_with_crc32() {
+        let record = Record::new(1, 2, Some(b"key".to_vec()), Some(b"value".to_vec()));
+        let encoded = encode_with(&record, 0, crc32fast::hash);
+        assert_same(&record, &read(&encoded).unwrap().unwrap());
+        let error = read(&encode_with(&record, 0, crc32c::crc32c)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_unknown_format_versions() {
+        let record = Record::new(1, 2, None, Some(b"value".to_vec()));
+        let error = read(&encode_with(&record, MAGIC + 1, crc32c::crc32c)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
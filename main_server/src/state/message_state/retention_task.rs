@@ -0,0 +1,30 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::sync::RwLock;
+
+use crate::state::topic_state::topic_state::Topic;
+
+/// Background loop deleting segments that fell out of their topic's retention
+/// settings, checked every `interval`. The store's lock is only held to take the
+/// segments out; their files are deleted after it is released.
+pub async fn run_retention(topics_data: Arc<RwLock<Topic>>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let expired = topics_data.write().await.messages_store.expire_segments();
+        if expired.is_empty() {
+            continue;
+        }
+        let res = tokio::task::spawn_blocking(move || {
+            expired
+                .iter()
+                .try_for_each(|segment| segment.delete_files())
+        })
+        .await;
+        match res {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => eprintln!("Failed to enforce retention; err = {:?}", e),
+            Err(e) => eprintln!("Retention task failed; err = {:?}", e),
+        }
+    }
+}
@@ -0,0 +1,734 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, ErrorKind, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::state::message_state::{
+    offset_index::{OffsetIndexEntry, read_offset_index, write_offset_index},
+    record::Record,
+    time_index::{TimeIndexEntry, read_time_index, write_time_index},
+};
+
+/// Appends records to the active segment of a partition, and entries to its index.
+///
+/// Every append goes straight to the file so an acknowledged record is at least in
+/// the OS page cache; the sync taken with `take_sync` is what makes it durable.
+pub struct SegmentWriter {
+    file: File,
+    index_file: File,
+    time_index_file: File,
+    size: u64,
+    dirty: bool,
+}
+
+impl SegmentWriter {
+    /// Opens the segment whose log is at `path`.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let open_for_append =
+            |path: PathBuf| OpenOptions::new().create(true).append(true).open(path);
+        let file = open_for_append(path.to_path_buf())?;
+        let index_file = open_for_append(path.with_extension("index"))?;
+        let time_index_file = open_for_append(path.with_extension("timeindex"))?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            file,
+            index_file,
+            time_index_file,
+            size,
+            dirty: false,
+        })
+    }
+
+    /// Writes `records` with a single write, so either all of them are appended or
+    /// none are.
+    pub fn append(&mut self, records: &[Record]) -> io::Result<()> {
+        let mut encoded = Vec::new();
+        for record in records {
+            encoded.extend_from_slice(&record.encode());
+        }
+        if let Err(e) = self.file.write_all(&encoded) {
+            // Drop whatever part of the records made it to the file so the next
+            // append does not land behind a torn record.
+            let _ = self.file.set_len(self.size);
+            return Err(e);
+        }
+        self.size += encoded.len() as u64;
+        self.dirty = true;
+        Ok(())
+    }
+
+    pub fn append_index_entries(&mut self, entries: &IndexEntries) -> io::Result<()> {
+        if let Some(offset_entry) = &entries.offset_entry {
+            self.index_file.write_all(&offset_entry.encode())?;
+        }
+        if let Some(time_entry) = &entries.time_entry {
+            self.time_index_file.write_all(&time_entry.encode())?;
+        }
+        Ok(())
+    }
+
+    /// The sync that makes the appends so far durable, to run once the store is
+    /// unlocked. Only the log itself is synced; stale indexes are rebuilt on
+    /// recovery.
+    pub fn take_sync(&mut self) -> io::Result<PendingSync> {
+        if !self.dirty {
+            return Ok(PendingSync(None));
+        }
+        let file = self.file.try_clone()?;
+        self.dirty = false;
+        Ok(PendingSync(Some(file)))
+    }
+}
+
+/// An fsync of a segment owed to the records appended to it, left for after the
+/// store is unlocked so that it does not hold up every other partition.
+pub struct PendingSync(Option<File>);
+
+impl PendingSync {
+    /// Nothing to sync.
+    pub fn none() -> Self {
+        Self(None)
+    }
+
+    pub async fn wait(self) -> io::Result<()> {
+        let Some(file) = self.0 else {
+            return Ok(());
+        };
+        tokio::task::spawn_blocking(move || file.sync_data())
+            .await
+            .map_err(io::Error::other)?
+    }
+}
+
+/// Sequentially reads records back from a partition segment file.
+pub struct SegmentReader {
+    reader: BufReader<File>,
+    /// Bytes of the file after the read position.
+    remaining: u64,
+}
+
+impl SegmentReader {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::open_at(path, 0)
+    }
+
+    /// Opens the segment with the read position at `position`, which must be the
+    /// start of a record.
+    pub fn open_at<P: AsRef<Path>>(path: P, position: u64) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let remaining = file.metadata()?.len().saturating_sub(position);
+        file.seek(SeekFrom::Start(position))?;
+        Ok(Self {
+            reader: BufReader::new(file),
+            remaining,
+        })
+    }
+
+    /// Opens the segment to read it only up to byte `end`, its size when records
+    /// may be appended past it concurrently.
+    pub fn open_until<P: AsRef<Path>>(path: P, end: u64) -> io::Result<Self> {
+        let mut reader = Self::open(path)?;
+        reader.remaining = reader.remaining.min(end);
+        Ok(reader)
+    }
+
+    pub fn next_record(&mut self) -> io::Result<Option<Record>> {
+        let record = Record::read_from(&mut self.reader, self.remaining)?;
+        if let Some(record) = &record {
+            self.remaining -= record.encoded_len() as u64;
+        }
+        Ok(record)
+    }
+
+    /// Scans forward to the first record at or after `offset`, or the batch holding
+    /// it; compaction may have removed `offset` itself.
+    pub fn find(&mut self, offset: i64) -> io::Result<Option<Record>> {
+        while let Some(record) = self.next_record()? {
+            if record.last_offset() >= offset {
+                return Ok(Some(record));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// File name of the segment whose first record has `base_offset`, zero padded so
+/// that a directory listing sorts segments in offset order.
+pub fn segment_file_name(base_offset: i32) -> String {
+    format!("{:020}.log", base_offset)
+}
+
+/// Extension of the file a segment is rewritten into by compaction before it
+/// replaces the original.
+pub const CLEANED_EXTENSION: &str = "cleaned";
+
+/// Inverse of `segment_file_name`; `None` for anything that is not a segment file.
+pub fn parse_segment_file_name(path: &Path) -> Option<i32> {
+    if path.extension().is_none_or(|extension| extension != "log") {
+        return None;
+    }
+    let stem = path.file_stem()?.to_str()?;
+    if stem.len() != 20 {
+        return None;
+    }
+    stem.parse::<i32>().ok()
+}
+
+/// Offset a log file of the newline delimited format used before segments had a
+/// binary layout ends at. Such a file, `<n>.log` without zero padding, holds the
+/// messages right before offset `n`, one per line.
+fn parse_legacy_segment_file_name(path: &Path) -> Option<i32> {
+    if path.extension().is_none_or(|extension| extension != "log") {
+        return None;
+    }
+    let stem = path.file_stem()?.to_str()?;
+    if stem.len() == 20 {
+        return None;
+    }
+    stem.parse::<i32>().ok()
+}
+
+/// Extension added to a legacy log file that could not be migrated.
+const UNMIGRATED_EXTENSION: &str = "unmigrated";
+
+/// Rewrites the newline delimited log files of a partition left by a broker from
+/// before segments had a binary layout into a single segment, then deletes them.
+/// Every line becomes a record without a key, stamped with its file's
+/// modification time. The segment is written to a `.cleaned` file that is renamed
+/// into place once complete, so a crash leaves the old files to be migrated again.
+///
+/// A file holding more lines than it has offsets, because some of its messages
+/// contained newlines, cannot be split back into its messages. It is set aside as
+/// `<n>.log.unmigrated` and its offsets are left out of the segment, as are all
+/// legacy files of a partition that already has segments.
+pub fn migrate_legacy_segments(partition_path: &Path) -> io::Result<()> {
+    let mut legacy_paths = Vec::new();
+    let mut segment_paths = Vec::new();
+    for entry in fs::read_dir(partition_path)? {
+        let path = entry?.path();
+        if let Some(end_offset) = parse_legacy_segment_file_name(&path) {
+            legacy_paths.push((end_offset, path));
+        } else if parse_segment_file_name(&path).is_some() {
+            segment_paths.push(path);
+        }
+    }
+    if legacy_paths.is_empty() {
+        return Ok(());
+    }
+    legacy_paths.sort();
+    let mut records = Vec::new();
+    let mut migrated_paths = Vec::new();
+    for (end_offset, path) in legacy_paths {
+        let contents = fs::read(&path)?;
+        if contents.is_empty() {
+            migrated_paths.push(path);
+            continue;
+        }
+        let timestamp = fs::metadata(&path)?
+            .modified()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as i64);
+        let lines: Vec<&[u8]> = contents
+            .strip_suffix(b"\n")
+            .unwrap_or(&contents)
+            .split(|&byte| byte == b'\n')
+            .collect();
+        let first_offset = end_offset as i64 - lines.len() as i64;
+        if first_offset < 0
+            || records
+                .last()
+                .is_some_and(|last: &Record| last.offset >= first_offset)
+        {
+            set_aside_legacy_file(&path)?;
+            continue;
+        }
+        records.extend(lines.into_iter().enumerate().map(|(i, line)| {
+            Record::new(
+                first_offset + i as i64,
+                timestamp,
+                None,
+                Some(line.to_vec()),
+            )
+        }));
+        migrated_paths.push(path);
+    }
+    let Some(first_record) = records.first() else {
+        for path in &migrated_paths {
+            fs::remove_file(path)?;
+        }
+        return Ok(());
+    };
+    let segment_path = partition_path.join(segment_file_name(first_record.offset as i32));
+    // A segment next to the legacy files is only expected from a migration that
+    // crashed before deleting them, and then it is the one about to be rewritten.
+    if segment_paths.iter().any(|path| *path != segment_path) {
+        eprintln!(
+            "{:?} holds both legacy log files and segments",
+            partition_path
+        );
+        for path in &migrated_paths {
+            set_aside_legacy_file(path)?;
+        }
+        return Ok(());
+    }
+    let cleaned_path = segment_path.with_extension(CLEANED_EXTENSION);
+    let mut cleaned_file = File::create(&cleaned_path)?;
+    for record in &records {
+        cleaned_file.write_all(&record.encode())?;
+    }
+    cleaned_file.sync_all()?;
+    fs::rename(&cleaned_path, &segment_path)?;
+    // Indexes are rebuilt from the segment when it is recovered.
+    for path in [
+        segment_path.with_extension("index"),
+        segment_path.with_extension("timeindex"),
+    ] {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    for path in &migrated_paths {
+        fs::remove_file(path)?;
+    }
+    eprintln!(
+        "Migrated {} records from legacy log files in {:?}",
+        records.len(),
+        partition_path
+    );
+    Ok(())
+}
+
+/// Renames a legacy log file that cannot be migrated out of the way, keeping it
+/// for an operator to recover by hand.
+fn set_aside_legacy_file(path: &Path) -> io::Result<()> {
+    let unmigrated_path = path.with_extension(format!("log.{}", UNMIGRATED_EXTENSION));
+    eprintln!(
+        "Cannot migrate legacy log file {:?}, moved it to {:?}",
+        path, unmigrated_path
+    );
+    fs::rename(path, unmigrated_path)
+}
+
+/// Index entries owed to the record about to be appended to a segment.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IndexEntries {
+    pub offset_entry: Option<OffsetIndexEntry>,
+    pub time_entry: Option<TimeIndexEntry>,
+}
+
+/// Bookkeeping for one segment file of a partition.
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub base_offset: i32,
+    /// Offset the next record appended to this segment would get.
+    pub next_offset: i32,
+    pub size: u64,
+    /// Broker time the segment was started, used for time based rolling.
+    pub created_at: i64,
+    /// Largest record timestamp in the segment, `i64::MIN` while it is empty.
+    pub max_timestamp: i64,
+    offset_of_max_timestamp: i32,
+    pub path: PathBuf,
+    /// In-memory copy of the segment's sparse `.index` file.
+    pub offset_index: Vec<OffsetIndexEntry>,
+    /// In-memory copy of the segment's sparse `.timeindex` file.
+    pub time_index: Vec<TimeIndexEntry>,
+    bytes_since_index_entry: u64,
+}
+
+impl Segment {
+    pub fn new(path: PathBuf, base_offset: i32, created_at: i64) -> Self {
+        Self {
+            base_offset,
+            next_offset: base_offset,
+            size: 0,
+            created_at,
+            max_timestamp: i64::MIN,
+            offset_of_max_timestamp: base_offset,
+            path,
+            offset_index: Vec::new(),
+            time_index: Vec::new(),
+            bytes_since_index_entry: 0,
+        }
+    }
+
+    pub fn index_path(&self) -> PathBuf {
+        self.path.with_extension("index")
+    }
+
+    pub fn time_index_path(&self) -> PathBuf {
+        self.path.with_extension("timeindex")
+    }
+
+    /// Removes the segment's log and index files.
+    pub fn delete_files(&self) -> io::Result<()> {
+        for path in [self.path.clone(), self.index_path(), self.time_index_path()] {
+            match fs::remove_file(path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the records of the closed segment that `keep` accepts to a `.cleaned`
+    /// file next to it and returns the segment they make up, which `swap_in_cleaned`
+    /// puts in place of this one. Returns `Ok(None)` after removing the file when no
+    /// record is kept. The segment itself is left untouched, so this can run while
+    /// it is being read.
+    pub fn write_cleaned<F: FnMut(&Record) -> bool>(
+        &self,
+        mut keep: F,
+        index_interval_bytes: u64,
+    ) -> io::Result<Option<Segment>> {
+        let cleaned_path = self.path.with_extension(CLEANED_EXTENSION);
+        let mut cleaned_file = File::create(&cleaned_path)?;
+        let mut cleaned = Segment::new(self.path.clone(), self.base_offset, self.created_at);
+        let mut reader = SegmentReader::open(&self.path)?;
+        while let Some(record) = reader.next_record()? {
+            if keep(&record) {
+                cleaned_file.write_all(&record.encode())?;
+                let index_entries = cleaned.next_index_entries(
+                    record.offset as i32,
+                    record.timestamp,
+                    index_interval_bytes,
+                );
+                cleaned.record_appended(&record, index_entries);
+            }
+        }
+        cleaned_file.sync_all()?;
+        drop(cleaned_file);
+        if cleaned.size == 0 {
+            fs::remove_file(&cleaned_path)?;
+            return Ok(None);
+        }
+        Ok(Some(cleaned))
+    }
+
+    /// Renames the `.cleaned` file written for this segment over its log. The old
+    /// indexes are removed first, so a crash leaves either the old log or the new
+    /// one, and recovery rebuilds the indexes of either.
+    pub fn swap_in_cleaned(&self) -> io::Result<()> {
+        for path in [self.index_path(), self.time_index_path()] {
+            match fs::remove_file(path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        fs::rename(self.path.with_extension(CLEANED_EXTENSION), &self.path)
+    }
+
+    pub fn write_indexes(&self) -> io::Result<()> {
+        write_offset_index(self.index_path(), &self.offset_index)?;
+        write_time_index(self.time_index_path(), &self.time_index)
+    }
+
+    pub fn record_count(&self) -> i32 {
+        self.next_offset - self.base_offset
+    }
+
+    /// The index entries the record about to be appended at `offset` should get. An
+    /// offset index entry is due once at least `index_interval_bytes` were written
+    /// since the previous one, and a time index entry goes along with it whenever
+    /// the segment's largest timestamp moved past the last time index entry.
+    pub fn next_index_entries(
+        &self,
+        offset: i32,
+        timestamp: i64,
+        index_interval_bytes: u64,
+    ) -> IndexEntries {
+        if self.bytes_since_index_entry < index_interval_bytes.max(1) {
+            return IndexEntries::default();
+        }
+        let offset_entry = OffsetIndexEntry {
+            relative_offset: (offset - self.base_offset) as u32,
+            position: self.size as u32,
+        };
+        let (max_timestamp, offset_of_max_timestamp) = if timestamp > self.max_timestamp {
+            (timestamp, offset)
+        } else {
+            (self.max_timestamp, self.offset_of_max_timestamp)
+        };
+        let time_entry = match self.time_index.last() {
+            Some(last_entry) if last_entry.timestamp >= max_timestamp => None,
+            _ => Some(TimeIndexEntry {
+                timestamp: max_timestamp,
+                relative_offset: (offset_of_max_timestamp - self.base_offset) as u32,
+            }),
+        };
+        IndexEntries {
+            offset_entry: Some(offset_entry),
+            time_entry,
+        }
+    }
+
+    /// The index entries of `records` when appended in order, see
+    /// `next_index_entries`, worked out without appending them.
+    pub fn index_entries_for(
+        &self,
+        records: &[Record],
+        index_interval_bytes: u64,
+    ) -> Vec<IndexEntries> {
+        // Only the last time index entry matters for the next ones.
+        let mut tail = Segment {
+            base_offset: self.base_offset,
+            next_offset: self.next_offset,
+            size: self.size,
+            created_at: self.created_at,
+            max_timestamp: self.max_timestamp,
+            offset_of_max_timestamp: self.offset_of_max_timestamp,
+            path: PathBuf::new(),
+            offset_index: Vec::new(),
+            time_index: self.time_index.last().copied().into_iter().collect(),
+            bytes_since_index_entry: self.bytes_since_index_entry,
+        };
+        records
+            .iter()
+            .map(|record| {
+                let index_entries = tail.next_index_entries(
+                    record.offset as i32,
+                    record.timestamp,
+                    index_interval_bytes,
+                );
+                tail.record_appended(record, index_entries);
+                index_entries
+            })
+            .collect()
+    }
+
+    pub fn record_appended(&mut self, record: &Record, index_entries: IndexEntries) {
+        let offset = record.offset as i32;
+        let timestamp = record.timestamp;
+        let record_len = record.encoded_len() as u64;
+        if let Some(offset_entry) = index_entries.offset_entry {
+            self.offset_index.push(offset_entry);
+            self.bytes_since_index_entry = 0;
+        }
+        if let Some(time_entry) = index_entries.time_entry {
+            self.time_index.push(time_entry);
+        }
+        if timestamp > self.max_timestamp {
+            self.max_timestamp = timestamp;
+            self.offset_of_max_timestamp = offset;
+        }
+        self.next_offset = record.last_offset() as i32 + 1;
+        self.size += record_len;
+        self.bytes_since_index_entry += record_len;
+    }
+
+    /// Loads a segment left behind by a previous run. Only the part after the last
+    /// offset index entry is scanned; the whole segment is when either index is
+    /// missing or unusable. In the active segment a record torn by a crash
+    /// mid-append is cut off so the segment ends at its last complete record; a bad
+    /// record in a closed segment is only reported and left for `verify-log`. The
+    /// indexes are rewritten if they changed.
+    pub fn recover(
+        path: PathBuf,
+        base_offset: i32,
+        default_created_at: i64,
+        index_interval_bytes: u64,
+        is_active: bool,
+    ) -> io::Result<Self> {
+        let file_len = fs::metadata(&path)?.len();
+        let mut segment = Self::new(path, base_offset, default_created_at);
+        let loaded_offset_index = read_offset_index(segment.index_path())?;
+        let loaded_time_index = read_time_index(segment.time_index_path())?;
+        let (mut offset_index, mut time_index) = match (loaded_offset_index, loaded_time_index) {
+            (Some(offset_index), Some(time_index)) => (offset_index, time_index),
+            _ => (Vec::new(), Vec::new()),
+        };
+        let mut index_changed = offset_index.is_empty();
+        let usable_entries = offset_index
+            .iter()
+            .enumerate()
+            .take_while(|(i, entry)| {
+                (entry.position as u64) < file_len
+                    && (*i == 0 || offset_index[i - 1].position < entry.position)
+            })
+            .count();
+        if usable_entries < offset_index.len() {
+            offset_index.truncate(usable_entries);
+            index_changed = true;
+        }
+        // Time index entries are only written next to an offset index entry, so
+        // anything past the last usable one is replayed by the scan below.
+        let last_indexed_offset = offset_index.last().map_or(0, |entry| entry.relative_offset);
+        let usable_time_entries = time_index
+            .iter()
+            .enumerate()
+            .take_while(|(i, entry)| {
+                entry.relative_offset <= last_indexed_offset
+                    && (*i == 0 || time_index[i - 1].timestamp < entry.timestamp)
+            })
+            .count();
+        if usable_time_entries < time_index.len() || offset_index.is_empty() {
+            time_index.truncate(if offset_index.is_empty() {
+                0
+            } else {
+                usable_time_entries
+            });
+            index_changed = true;
+        }
+
+        if let Ok(Some(first_record)) = SegmentReader::open(&segment.path)?.next_record() {
+            segment.created_at = first_record.timestamp;
+        }
+        if let Some(last_entry) = offset_index.last() {
+            segment.size = last_entry.position as u64;
+            segment.next_offset = base_offset + last_entry.relative_offset as i32;
+        }
+        if let Some(last_entry) = time_index.last() {
+            segment.max_timestamp = last_entry.timestamp;
+            segment.offset_of_max_timestamp = base_offset + last_entry.relative_offset as i32;
+        }
+        segment.offset_index = offset_index;
+        segment.time_index = time_index;
+
+        let mut reader = SegmentReader::open_at(&segment.path, segment.size)?;
+        loop {
+            match reader.next_record() {
+                Ok(Some(record)) => {
+                    let offset = record.offset as i32;
+                    let index_entries =
+                        segment.next_index_entries(offset, record.timestamp, index_interval_bytes);
+                    index_changed |= index_entries.offset_entry.is_some();
+                    segment.record_appended(&record, index_entries);
+                }
+                // Too little is left for even a length prefix.
+                Ok(None) if is_active && segment.size < file_len => {
+                    eprintln!(
+                        "Truncating {:?} to its last complete record; torn length prefix",
+                        segment.path
+                    );
+                    segment.truncate_to_size()?;
+                    index_changed = true;
+                    break;
+                }
+                Ok(None) => break,
+                Err(e) if e.kind() == ErrorKind::InvalidData && !is_active => {
+                    eprintln!(
+                        "Corrupted record at byte {} of {:?}, run verify-log to repair it; err = {:?}",
+                        segment.size, segment.path, e
+                    );
+                    segment.size = file_len;
+                    break;
+                }
+                Err(e) if e.kind() == ErrorKind::InvalidData => {
+                    eprintln!(
+                        "Truncating {:?} to its last complete record; err = {:?}",
+                        segment.path, e
+                    );
+                    segment.truncate_to_size()?;
+                    index_changed = true;
+                    break;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        if index_changed {
+            segment.write_indexes()?;
+        }
+        Ok(segment)
+    }
+
+    /// Cuts the log file back to the records recovered so far, and the indexes
+    /// with it.
+    fn truncate_to_size(&mut self) -> io::Result<()> {
+        let file = OpenOptions::new().write(true).open(&self.path)?;
+        file.set_len(self.size)?;
+        file.sync_all()?;
+        let size = self.size;
+        let next_offset = self.next_offset;
+        let base_offset = self.base_offset;
+        self.offset_index
+            .retain(|entry| (entry.position as u64) < size);
+        self.time_index
+            .retain(|entry| base_offset + (entry.relative_offset as i32) < next_offset);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Directory for one test's segment files, emptied first.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("segment-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn records(count: i64) -> Vec<Record> {
+        (0..count)
+            .map(|offset| Record::new(offset, 1_000 + offset, None, Some(vec![b'v'; 100])))
+            .collect()
+    }
+
+    /// Writes `records` to a new segment followed by `torn_bytes` bytes of the
+    /// next record, as a crash in the middle of an append would leave it.
+    fn write_torn_segment(dir: &Path, records: &[Record], torn_bytes: usize) -> (PathBuf, u64) {
+        let path = dir.join(segment_file_name(0));
+        let mut writer = SegmentWriter::open(&path).unwrap();
+        writer.append(records).unwrap();
+        let complete_len = fs::metadata(&path).unwrap().len();
+        let torn = Record::new(records.len() as i64, 0, None, Some(vec![b't'; 100])).encode();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&torn[..torn_bytes]).unwrap();
+        (path, complete_len)
+    }
+
+    fn read_offsets(path: &Path) -> Vec<i64> {
+        let mut reader = SegmentReader::open(path).unwrap();
+        let mut offsets = Vec::new();
+        while let Some(record) = reader.next_record().unwrap() {
+            offsets.push(record.offset);
+        }
+        offsets
+    }
+
+    #[test]
+    fn recover_truncates_torn_last_record() {
+        // Torn inside the record body and inside its length prefix.
+        for torn_bytes in [50, 2] {
+            let dir = test_dir(&format!("torn-{torn_bytes}"));
+            let (path, complete_len) = write_torn_segment(&dir, &records(3), torn_bytes);
+
+            let segment = Segment::recover(path.clone(), 0, 0, 64, true).unwrap();
+            assert_eq!(segment.next_offset, 3);
+            assert_eq!(segment.size, complete_len);
+            assert_eq!(fs::metadata(&path).unwrap().len(), complete_len);
+            assert!(
+                segment
+                    .offset_index
+                    .iter()
+                    .all(|entry| (entry.position as u64) < complete_len)
+            );
+
+            // Appends continue right after the last complete record.
+            let mut writer = SegmentWriter::open(&path).unwrap();
+            writer
+                .append(&[Record::new(3, 2_000, None, Some(b"next".to_vec()))])
+                .unwrap();
+            assert_eq!(read_offsets(&path), vec![0, 1, 2, 3]);
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn recover_keeps_complete_segment() {
+        let dir = test_dir("complete");
+        let (path, complete_len) = write_torn_segment(&dir, &records(3), 0);
+
+        let segment = Segment::recover(path.clone(), 0, 0, 64, true).unwrap();
+        assert_eq!(segment.next_offset, 3);
+        assert_eq!(segment.size, complete_len);
+        assert_eq!(segment.created_at, 1_000);
+        assert_eq!(segment.max_timestamp, 1_002);
+        assert_eq!(read_offsets(&path), vec![0, 1, 2]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
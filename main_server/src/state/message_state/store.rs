@@ -1,9 +1,8 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     env,
-    fs::File,
-    io::{BufRead, BufReader},
-    path::Path,
+    io::ErrorKind,
+    path::{Path, PathBuf},
 };
 
 use tokio::{
@@ -11,176 +10,868 @@ use tokio::{
     io::AsyncWriteExt,
 };
 
-#[derive(Debug)]
-pub struct MessageAndTotalMessageCount {
-    pub messages: Vec<Vec<u8>>,
+use crate::state::{
+    config::{
+        broker_config::FsyncPolicy,
+        topic_config::{CleanupPolicy, TopicConfig},
+    },
+    helpers::helper::current_time_millis,
+    message_state::{
+        compaction::{CompactedPartition, CompactionJob},
+        compression::Codec,
+        offset_index::lookup_position,
+        record::Record,
+        segment::{
+            CLEANED_EXTENSION, IndexEntries, PendingSync, Segment, SegmentReader, SegmentWriter,
+            migrate_legacy_segments, parse_segment_file_name, segment_file_name,
+        },
+        store_error::StoreError,
+        time_index::lookup_relative_offset,
+    },
+    topic_state::consumer_group::DEFAULT_GROUP_ID,
+};
+
+/// Key, value and, when the producer supplied one, timestamp of a message.
+pub type MessageToAppend = (Option<Vec<u8>>, Option<Vec<u8>>, Option<i64>);
+
+pub struct PartitionLog {
+    /// Every segment of the partition keyed by base offset; the last one is active.
+    segments: BTreeMap<i32, Segment>,
+    active_writer: Option<SegmentWriter>,
+    /// First offset still on disk; everything below it was removed by retention
+    /// or compaction.
+    log_start_offset: i32,
     total_messages: i32,
+    /// Committed offset of every consumer group that committed one.
+    committed_offsets: HashMap<String, i32>,
+    /// Base offset of the active segment when compaction last cleaned the
+    /// partition; the closed segments below it need no cleaning until a kept
+    /// tombstone is due to be dropped at `tombstones_expire_at`.
+    cleaned_up_to: i32,
+    tombstones_expire_at: Option<i64>,
+}
+
+impl PartitionLog {
+    /// The segments holding `offset` and those after it, each with the byte to
+    /// start reading it from.
+    fn segment_reads(&self, offset: i32) -> Vec<(PathBuf, u64)> {
+        let start = self
+            .segments
+            .range(..=offset)
+            .next_back()
+            .map_or(self.log_start_offset, |(base_offset, _)| *base_offset);
+        self.segments
+            .range(start..)
+            .map(|(_, segment)| {
+                let relative_offset =
+                    (offset.max(segment.base_offset) - segment.base_offset) as u32;
+                let position = lookup_position(&segment.offset_index, relative_offset);
+                (segment.path.clone(), position)
+            })
+            .collect()
+    }
 }
 
 pub struct MessageStore {
-    pub store: HashMap<String, HashMap<i32, MessageAndTotalMessageCount>>,
+    pub store: HashMap<String, HashMap<i32, PartitionLog>>,
+    configs: HashMap<String, TopicConfig>,
+    fsync_policy: FsyncPolicy,
+    /// Syncs of segments rolled since the last `FsyncPolicy::Interval` pass.
+    rolled_syncs: Vec<PendingSync>,
 }
 
 impl MessageStore {
-    pub fn new() -> Self {
+    pub fn new(fsync_policy: FsyncPolicy) -> Self {
         Self {
             store: HashMap::new(),
+            configs: HashMap::new(),
+            fsync_policy,
+            rolled_syncs: Vec::new(),
         }
     }
 
-    pub fn add_topic(&mut self, topic_name: String, partitions: i32) {
+    pub fn add_topic(&mut self, topic_name: String, partitions: i32, config: TopicConfig) {
         if self.store.contains_key(&topic_name) {
             return;
         }
-        let partitions_count;
-        if partitions < 1 {
-            partitions_count = 1;
-        } else {
-            partitions_count = partitions;
-        }
+        let partitions_count = if partitions < 1 { 1 } else { partitions };
         let mut partitions_map = HashMap::new();
         for i in 0..partitions_count {
-            let msgs_and_total_count = MessageAndTotalMessageCount {
-                messages: Vec::new(),
+            let partition_log = PartitionLog {
+                segments: BTreeMap::new(),
+                active_writer: None,
+                log_start_offset: 0,
                 total_messages: 0,
+                committed_offsets: HashMap::new(),
+                cleaned_up_to: 0,
+                tombstones_expire_at: None,
+            };
+            partitions_map.insert(i, partition_log);
+        }
+        self.configs.insert(topic_name.clone(), config);
+        self.store.insert(topic_name, partitions_map);
+    }
+
+    /// Rebuilds the partition state of a topic that already has data under `logs/`.
+    /// `total_messages` continues after the last complete record found in its segments
+    /// and committed offsets are read back from `offsets/<topic>/<group>/`, dropping
+    /// those of records the log no longer reaches.
+    pub async fn recover_topic(
+        &mut self,
+        topic_name: String,
+        partitions: i32,
+        config: TopicConfig,
+    ) -> std::io::Result<()> {
+        if self.store.contains_key(&topic_name) {
+            return Ok(());
+        }
+        let logs_path = env::current_dir()?.join("logs").join(&topic_name);
+        let offsets_path = env::current_dir()?.join("offsets").join(&topic_name);
+        let mut group_paths = Vec::new();
+        let mut legacy_offset_paths = Vec::new();
+        match fs::read_dir(&offsets_path).await {
+            Ok(mut group_entries) => {
+                while let Some(group_entry) = group_entries.next_entry().await? {
+                    let Some(name) = group_entry.file_name().to_str().map(str::to_string) else {
+                        continue;
+                    };
+                    if group_entry.file_type().await?.is_dir() {
+                        group_paths.push((name, group_entry.path()));
+                    } else if name.parse::<i32>().is_ok() {
+                        legacy_offset_paths.push((name, group_entry.path()));
+                    }
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        // Offsets committed before consumer groups existed sit directly under the
+        // topic; they become the offsets of the default group.
+        if !legacy_offset_paths.is_empty() {
+            let default_group_path = offsets_path.join(DEFAULT_GROUP_ID);
+            fs::create_dir_all(&default_group_path).await?;
+            for (partition, legacy_path) in legacy_offset_paths {
+                let path = default_group_path.join(partition);
+                if fs::try_exists(&path).await? {
+                    fs::remove_file(legacy_path).await?;
+                } else {
+                    fs::rename(legacy_path, path).await?;
+                }
+            }
+            if !group_paths
+                .iter()
+                .any(|(group_id, _)| group_id == DEFAULT_GROUP_ID)
+            {
+                group_paths.push((DEFAULT_GROUP_ID.to_string(), default_group_path));
+            }
+        }
+        let mut partitions_map = HashMap::new();
+        for i in 0..partitions {
+            let segments = self
+                .recover_segments(
+                    logs_path.join(format!("{}", i)),
+                    config.index_interval_bytes,
+                )
+                .await?;
+            let total_messages = segments
+                .values()
+                .next_back()
+                .map_or(0, |segment| segment.next_offset);
+            let log_start_offset = segments.keys().next().copied().unwrap_or(total_messages);
+            let mut committed_offsets = HashMap::new();
+            for (group_id, group_path) in &group_paths {
+                let offset_path = group_path.join(format!("{}", i));
+                match self.read_from_file(&offset_path).await? {
+                    // Left over from records that are gone, the group starts over.
+                    Some(offset) if offset >= total_messages => {
+                        eprintln!(
+                            "Dropping committed offset {} of group {} in {}/{}, the log ends before it",
+                            offset, group_id, topic_name, i
+                        );
+                        fs::remove_file(&offset_path).await?;
+                    }
+                    Some(offset) => {
+                        committed_offsets.insert(group_id.clone(), offset);
+                    }
+                    None => {}
+                }
+            }
+            let partition_log = PartitionLog {
+                segments,
+                active_writer: None,
+                log_start_offset,
+                total_messages,
+                committed_offsets,
+                cleaned_up_to: 0,
+                tombstones_expire_at: None,
             };
-            partitions_map.insert(i, msgs_and_total_count);
+            partitions_map.insert(i, partition_log);
         }
+        self.configs.insert(topic_name.clone(), config);
         self.store.insert(topic_name, partitions_map);
+        Ok(())
+    }
+
+    async fn recover_segments<P: AsRef<Path>>(
+        &self,
+        partition_path: P,
+        index_interval_bytes: u64,
+    ) -> std::io::Result<BTreeMap<i32, Segment>> {
+        migrate_legacy_segments(partition_path.as_ref())?;
+        let mut segment_paths = BTreeMap::new();
+        let mut entries = fs::read_dir(partition_path).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == CLEANED_EXTENSION)
+            {
+                // Compaction did not get to swap this in, the original is intact.
+                fs::remove_file(&path).await?;
+                continue;
+            }
+            if let Some(base_offset) = parse_segment_file_name(&path) {
+                segment_paths.insert(base_offset, path);
+            }
+        }
+        let active_base_offset = segment_paths.keys().next_back().copied();
+        let mut segments = BTreeMap::new();
+        for (base_offset, path) in segment_paths {
+            let segment = Segment::recover(
+                path,
+                base_offset,
+                current_time_millis(),
+                index_interval_bytes,
+                Some(base_offset) == active_base_offset,
+            )?;
+            segments.insert(base_offset, segment);
+        }
+        Ok(segments)
     }
 
     pub fn delete_topic(&mut self, topic_name: &str) {
         self.store.remove(topic_name);
+        self.configs.remove(topic_name);
     }
 
-    pub fn write_to_cache(
+    /// Appends a message to the active segment of the partition and returns its
+    /// offset and timestamp. The record is stamped with `timestamp` when the producer
+    /// supplied one and with the broker time otherwise. With
+    /// `FsyncPolicy::EveryMessage` the record is on disk once the returned sync is
+    /// done, which is left for after the store is unlocked; consumers may read it
+    /// before then.
+    pub async fn append_message(
         &mut self,
         topic_name: &str,
         partition: i32,
-        message: Vec<u8>,
-    ) -> (i32, i32) {
-        self.store
-            .get_mut(topic_name)
-            .unwrap()
-            .get_mut(&partition)
-            .unwrap()
-            .messages
-            .push(message);
+        key: Option<Vec<u8>>,
+        message: Option<Vec<u8>>,
+        timestamp: Option<i64>,
+    ) -> std::io::Result<((i32, i64), PendingSync)> {
+        let (appended, sync) = self
+            .append_messages(topic_name, partition, vec![(key, message, timestamp)])
+            .await?;
+        Ok((appended[0], sync))
+    }
 
-        self.store
-            .get_mut(topic_name)
-            .unwrap()
-            .get_mut(&partition)
-            .unwrap()
-            .total_messages += 1;
+    /// Appends `messages` to the partition in one write: either all of them end up in the log or none do. They
+    /// all go to the same segment, the active segment is only rolled before the
+    /// write. Returns the offset and timestamp of every message.
+    pub async fn append_messages(
+        &mut self,
+        topic_name: &str,
+        partition: i32,
+        messages: Vec<MessageToAppend>,
+    ) -> std::io::Result<(Vec<(i32, i64)>, PendingSync)> {
+        let first_offset = self.next_offset(topic_name, partition);
+        let now = current_time_millis();
+        let records = (first_offset..)
+            .zip(messages)
+            .map(|(offset, (key, message, timestamp))| {
+                Record::new(offset as i64, timestamp.unwrap_or(now), key, message)
+            })
+            .collect();
+        self.append_records(topic_name, partition, records).await
+    }
 
-        let cache_size = self
-            .store
-            .get(topic_name)
-            .unwrap()
-            .get(&partition)
-            .unwrap()
-            .messages
-            .len() as i32;
+    /// Appends `record_count` records compressed by the producer with `codec` as a
+    /// single batch, stored as sent, and returns the offset of its first record and
+    /// the batch timestamp. The batch is decompressed once to make sure it holds what
+    /// it claims to.
+    pub async fn append_batch(
+        &mut self,
+        topic_name: &str,
+        partition: i32,
+        codec: Codec,
+        record_count: i32,
+        compressed: &[u8],
+        timestamp: Option<i64>,
+    ) -> Result<((i32, i64), PendingSync), StoreError> {
+        if codec == Codec::None || record_count < 1 {
+            return Err(StoreError::InvalidBatch {
+                reason: "a batch needs a compression codec and at least one record".to_string(),
+            });
+        }
+        let offset = self.next_offset(topic_name, partition);
+        let timestamp = timestamp.unwrap_or_else(current_time_millis);
+        let record = Record::batch(offset as i64, timestamp, codec, record_count, compressed);
+        if let Err(e) = record.unpack() {
+            return Err(StoreError::InvalidBatch {
+                reason: e.to_string(),
+            });
+        }
+        let (appended, sync) = self
+            .append_records(topic_name, partition, vec![record])
+            .await?;
+        Ok((appended[0], sync))
+    }
 
-        let total_messages = self
-            .store
+    fn next_offset(&self, topic_name: &str, partition: i32) -> i32 {
+        self.store
             .get(topic_name)
             .unwrap()
             .get(&partition)
             .unwrap()
-            .total_messages;
-        return (cache_size, total_messages);
+            .total_messages
     }
 
-    pub fn clear_vec_and_return(&mut self, topic_name: &str, partition: i32) -> Vec<Vec<u8>> {
-        let messages = &mut self
+    /// Writes `records` to the active segment, rolling it first when it is full,
+    /// with the file I/O off the async runtime. Returns the sync that makes them
+    /// durable under `FsyncPolicy::EveryMessage`.
+    async fn append_records(
+        &mut self,
+        topic_name: &str,
+        partition: i32,
+        records: Vec<Record>,
+    ) -> std::io::Result<(Vec<(i32, i64)>, PendingSync)> {
+        let partition_path = self.partition_path(topic_name, partition)?;
+        let config = self.configs.get(topic_name).unwrap();
+        let partition_log = self
             .store
             .get_mut(topic_name)
             .unwrap()
             .get_mut(&partition)
-            .unwrap()
-            .messages;
-        std::mem::take(messages)
+            .unwrap();
+        let offset = records[0].offset as i32;
+        let now = current_time_millis();
+        let records_len: u64 = records
+            .iter()
+            .map(|record| record.encoded_len() as u64)
+            .sum();
+        let needs_roll = match partition_log.segments.values().next_back() {
+            None => true,
+            Some(active) => {
+                active.record_count() > 0
+                    && (active.size + records_len > config.segment_bytes.min(u32::MAX as u64)
+                        || active.record_count() >= config.segment_records
+                        || now - active.created_at >= config.segment_ms)
+            }
+        };
+        if needs_roll {
+            // Every append to it was synced already under `EveryMessage`, and
+            // `OsManaged` leaves it to the OS.
+            if let Some(mut previous_writer) = partition_log.active_writer.take()
+                && let FsyncPolicy::Interval(_) = self.fsync_policy
+            {
+                self.rolled_syncs.push(previous_writer.take_sync()?);
+            }
+            let segment = Segment::new(partition_path.join(segment_file_name(offset)), offset, now);
+            partition_log.segments.insert(offset, segment);
+        }
+        let active = partition_log.segments.values_mut().next_back().unwrap();
+        let index_entries = active.index_entries_for(&records, config.index_interval_bytes);
+        let writer = partition_log.active_writer.take();
+        let path = active.path.clone();
+        let (writer, records, appended) = tokio::task::spawn_blocking(move || {
+            let mut writer = match writer {
+                Some(writer) => writer,
+                None => match SegmentWriter::open(&path) {
+                    Ok(writer) => writer,
+                    Err(e) => return (None, records, Err(e)),
+                },
+            };
+            if let Err(e) = writer.append(&records) {
+                return (Some(writer), records, Err(e));
+            }
+            // The indexes are only accelerators, the records are already in the log.
+            let indexed = index_entries
+                .iter()
+                .position(|entries| match writer.append_index_entries(entries) {
+                    Ok(()) => false,
+                    Err(e) => {
+                        eprintln!(
+                            "Failed to append to the indexes of {:?}; err = {:?}",
+                            path, e
+                        );
+                        true
+                    }
+                })
+                .unwrap_or(index_entries.len());
+            (Some(writer), records, Ok((index_entries, indexed)))
+        })
+        .await
+        .map_err(std::io::Error::other)?;
+        partition_log.active_writer = writer;
+        let (index_entries, indexed) = appended?;
+        for (i, (record, index_entries)) in records.iter().zip(index_entries).enumerate() {
+            let index_entries = if i < indexed {
+                index_entries
+            } else {
+                IndexEntries::default()
+            };
+            active.record_appended(record, index_entries);
+            partition_log.total_messages += record.record_count();
+        }
+        let sync = match self.fsync_policy {
+            FsyncPolicy::EveryMessage => {
+                partition_log.active_writer.as_mut().unwrap().take_sync()?
+            }
+            _ => PendingSync::none(),
+        };
+        Ok((
+            records
+                .iter()
+                .map(|record| (record.offset as i32, record.timestamp))
+                .collect(),
+            sync,
+        ))
     }
 
+    /// Takes the syncs owed by every segment with unsynced appends, to run once the
+    /// store is unlocked.
+    pub fn take_syncs(&mut self) -> std::io::Result<Vec<PendingSync>> {
+        let mut syncs = std::mem::take(&mut self.rolled_syncs);
+        for partitions in self.store.values_mut() {
+            for partition_log in partitions.values_mut() {
+                if let Some(writer) = partition_log.active_writer.as_mut() {
+                    syncs.push(writer.take_sync()?);
+                }
+            }
+        }
+        Ok(syncs)
+    }
+
+    /// Takes, oldest first, the closed segments of every partition that fall
+    /// outside the topic's `retention_ms` or `retention_bytes` out of the partition,
+    /// and moves its log start offset up to the first segment left. Returns them so
+    /// that their files are deleted once the store is unlocked. Compacted topics are
+    /// left to compaction.
+    pub fn expire_segments(&mut self) -> Vec<Segment> {
+        let now = current_time_millis();
+        let mut expired = Vec::new();
+        for (topic_name, partitions) in self.store.iter_mut() {
+            let config = self.configs.get(topic_name).unwrap();
+            if config.cleanup_policy == CleanupPolicy::Compact {
+                continue;
+            }
+            for partition_log in partitions.values_mut() {
+                let mut total_size: u64 = partition_log
+                    .segments
+                    .values()
+                    .map(|segment| segment.size)
+                    .sum();
+                while partition_log.segments.len() > 1 {
+                    let oldest = partition_log.segments.values().next().unwrap();
+                    let expired_by_time = config.retention_ms >= 0
+                        && oldest.max_timestamp < now - config.retention_ms;
+                    let expired_by_size = config.retention_bytes >= 0
+                        && total_size - oldest.size >= config.retention_bytes as u64;
+                    if !expired_by_time && !expired_by_size {
+                        break;
+                    }
+                    let (_, oldest) = partition_log.segments.pop_first().unwrap();
+                    total_size -= oldest.size;
+                    expired.push(oldest);
+                    partition_log.log_start_offset = *partition_log.segments.keys().next().unwrap();
+                }
+            }
+        }
+        expired
+    }
+
+    /// A job for every partition of a compacted topic with closed segments that
+    /// were not cleaned since they were closed, or with a kept tombstone now due
+    /// to be dropped.
+    pub fn compaction_jobs(&self) -> Vec<CompactionJob> {
+        let now = current_time_millis();
+        let mut jobs = Vec::new();
+        for (topic_name, partitions) in &self.store {
+            let config = self.configs.get(topic_name).unwrap();
+            if config.cleanup_policy != CleanupPolicy::Compact {
+                continue;
+            }
+            for (partition, partition_log) in partitions {
+                if partition_log.segments.len() < 2 {
+                    continue;
+                }
+                let active_base_offset = *partition_log.segments.keys().next_back().unwrap();
+                let tombstones_expired = partition_log
+                    .tombstones_expire_at
+                    .is_some_and(|expire_at| expire_at <= now);
+                if active_base_offset <= partition_log.cleaned_up_to && !tombstones_expired {
+                    continue;
+                }
+                jobs.push(CompactionJob {
+                    topic_name: topic_name.clone(),
+                    partition: *partition,
+                    segments: partition_log.segments.values().cloned().collect(),
+                    delete_retention_ms: config.delete_retention_ms,
+                    index_interval_bytes: config.index_interval_bytes,
+                });
+            }
+        }
+        jobs
+    }
+
+    /// Puts the segments a compaction job cleaned in place of the originals, and
+    /// takes out those it left empty. A segment that changed since the job was
+    /// taken, which only happens when its topic was deleted, is left as it is.
+    /// Returns the files to delete once the store is unlocked.
+    pub fn apply_compaction(
+        &mut self,
+        compacted: CompactedPartition,
+    ) -> std::io::Result<Vec<PathBuf>> {
+        let mut obsolete_files = Vec::new();
+        let partition_log = self
+            .store
+            .get_mut(&compacted.topic_name)
+            .and_then(|partitions| partitions.get_mut(&compacted.partition));
+        let Some(partition_log) = partition_log else {
+            for (original, _) in compacted.cleaned {
+                obsolete_files.push(original.path.with_extension(CLEANED_EXTENSION));
+            }
+            return Ok(obsolete_files);
+        };
+        for (original, cleaned) in compacted.cleaned {
+            let unchanged = partition_log
+                .segments
+                .get(&original.base_offset)
+                .is_some_and(|segment| {
+                    segment.path == original.path
+                        && segment.size == original.size
+                        && segment.next_offset == original.next_offset
+                });
+            match cleaned {
+                _ if !unchanged => {
+                    obsolete_files.push(original.path.with_extension(CLEANED_EXTENSION));
+                }
+                Some(cleaned) => {
+                    cleaned.swap_in_cleaned()?;
+                    if let Err(e) = cleaned.write_indexes() {
+                        // Recovery rebuilds them from the log.
+                        eprintln!(
+                            "Failed to write the indexes of {:?}; err = {:?}",
+                            cleaned.path, e
+                        );
+                    }
+                    partition_log.segments.insert(original.base_offset, cleaned);
+                }
+                None => {
+                    partition_log.segments.remove(&original.base_offset);
+                    obsolete_files.extend([
+                        original.path.clone(),
+                        original.index_path(),
+                        original.time_index_path(),
+                    ]);
+                }
+            }
+        }
+        partition_log.log_start_offset = *partition_log.segments.keys().next().unwrap();
+        partition_log.cleaned_up_to = compacted.active_base_offset;
+        partition_log.tombstones_expire_at = compacted.tombstones_expire_at;
+        Ok(obsolete_files)
+    }
+
+    /// The first record at or after `offset`, which is a later one when compaction
+    /// removed `offset`. `Ok(None)` when nothing was written at `offset` yet.
+    ///
+    /// A compressed batch is returned whole unless `decompress` is set, in which
+    /// case it is unpacked and only the record at `offset` is returned.
     pub async fn get_message_by_offset(
         &self,
         partition: &i32,
         topic: &str,
         offset: i32,
-    ) -> Option<Vec<u8>> {
-        let total_messages = self
-            .store
-            .get(topic)
-            .unwrap()
-            .get(partition)
-            .unwrap()
-            .total_messages;
-        if total_messages <= offset {
-            return None;
-        }
-
-        let cache_start_offset = (total_messages / 10) * 10;
-        if offset >= cache_start_offset {
-            let message = self
-                .store
-                .get(topic)
-                .unwrap()
-                .get(&partition)
-                .unwrap()
-                .messages
-                .get((offset % 10) as usize);
-            if message.is_none() {
-                return None;
-            } else {
-                let message = message.unwrap().clone();
-                return Some(message);
+        decompress: bool,
+    ) -> Result<Option<Record>, StoreError> {
+        let partition_log = self.store.get(topic).unwrap().get(partition).unwrap();
+        if offset < partition_log.log_start_offset {
+            return Err(StoreError::OffsetOutOfRange {
+                offset,
+                log_start_offset: partition_log.log_start_offset,
+                high_watermark: partition_log.total_messages,
+            });
+        }
+        if partition_log.total_messages <= offset {
+            return Ok(None);
+        }
+        let record = match self.read_record(offset, topic, partition).await {
+            Ok(Some(record)) if decompress && record.codec != Codec::None => {
+                record.unpack().map(|records| {
+                    records
+                        .into_iter()
+                        .find(|record| record.offset >= offset as i64)
+                })
             }
-        } else {
-            let message = self.read_line(offset, topic, partition).await;
-            return Some(message);
+            record => record,
+        };
+        match record {
+            Ok(record) => Ok(record),
+            Err(e) if e.kind() == ErrorKind::InvalidData => {
+                eprintln!(
+                    "Corrupted record at offset {} of {}/{}; err = {:?}",
+                    offset, topic, partition, e
+                );
+                Err(StoreError::CorruptRecord { offset })
+            }
+            Err(e) => Err(StoreError::Io(e)),
         }
     }
 
-    async fn read_line(&self, offset: i32, topic: &str, partition: &i32) -> Vec<u8> {
-        let file_with_data = (offset / 10) * 10;
-        let file_path = env::current_dir()
-            .unwrap()
+    async fn read_record(
+        &self,
+        offset: i32,
+        topic: &str,
+        partition: &i32,
+    ) -> std::io::Result<Option<Record>> {
+        let partition_log = self.store.get(topic).unwrap().get(partition).unwrap();
+        let reads = partition_log.segment_reads(offset);
+        tokio::task::spawn_blocking(move || {
+            for (path, position) in reads {
+                if let Some(record) =
+                    SegmentReader::open_at(&path, position)?.find(offset as i64)?
+                {
+                    return Ok(Some(record));
+                }
+            }
+            Ok(None)
+        })
+        .await
+        .map_err(std::io::Error::other)?
+    }
+
+    /// Records from `offset` on, in offset order, until `max_records` records or
+    /// `max_bytes` bytes were collected. The first record is returned even when it is
+    /// larger than `max_bytes` so that a consumer always makes progress. Batches are
+    /// unpacked when `decompress` is set and otherwise returned whole, counting as one
+    /// record. A corrupted record ends the fetch, and is only reported when it is the
+    /// first one.
+    pub async fn fetch_records(
+        &self,
+        partition: &i32,
+        topic: &str,
+        offset: i32,
+        max_records: i32,
+        max_bytes: i32,
+        decompress: bool,
+    ) -> Result<Vec<Record>, StoreError> {
+        let partition_log = self.store.get(topic).unwrap().get(partition).unwrap();
+        if offset < partition_log.log_start_offset || offset > partition_log.total_messages {
+            return Err(StoreError::OffsetOutOfRange {
+                offset,
+                log_start_offset: partition_log.log_start_offset,
+                high_watermark: partition_log.total_messages,
+            });
+        }
+        let reads = partition_log.segment_reads(offset);
+        let topic = topic.to_string();
+        let partition = *partition;
+        tokio::task::spawn_blocking(move || {
+            let mut records = Vec::new();
+            let mut bytes = 0;
+            let corrupted = |records: &Vec<Record>, e: std::io::Error| {
+                if e.kind() != ErrorKind::InvalidData {
+                    return Err(StoreError::Io(e));
+                }
+                eprintln!(
+                    "Corrupted record at offset {} of {}/{}; err = {:?}",
+                    records
+                        .last()
+                        .map_or(offset as i64, |record| record.offset + 1),
+                    topic,
+                    partition,
+                    e
+                );
+                if records.is_empty() {
+                    Err(StoreError::CorruptRecord { offset })
+                } else {
+                    Ok(())
+                }
+            };
+            'segments: for (path, position) in reads {
+                let mut reader = SegmentReader::open_at(&path, position)?;
+                loop {
+                    let record = match reader.next_record() {
+                        Ok(Some(record)) => record,
+                        Ok(None) => break,
+                        Err(e) => {
+                            corrupted(&records, e)?;
+                            break 'segments;
+                        }
+                    };
+                    if record.last_offset() < offset as i64 {
+                        continue;
+                    }
+                    let unpacked = if decompress && record.codec != Codec::None {
+                        match record.unpack() {
+                            Ok(unpacked) => unpacked
+                                .into_iter()
+                                .filter(|record| record.offset >= offset as i64)
+                                .collect(),
+                            Err(e) => {
+                                corrupted(&records, e)?;
+                                break 'segments;
+                            }
+                        }
+                    } else {
+                        vec![record]
+                    };
+                    for record in unpacked {
+                        let record_len = record.encoded_len();
+                        if records.len() >= max_records as usize
+                            || (!records.is_empty() && bytes + record_len > max_bytes as usize)
+                        {
+                            break 'segments;
+                        }
+                        bytes += record_len;
+                        records.push(record);
+                    }
+                }
+            }
+            Ok(records)
+        })
+        .await
+        .map_err(|e| StoreError::Io(std::io::Error::other(e)))?
+    }
+
+    /// Earliest offset in the partition whose record timestamp is at or after
+    /// `timestamp`, `Ok(None)` when every record is older.
+    pub async fn get_offset_for_timestamp(
+        &self,
+        partition: &i32,
+        topic: &str,
+        timestamp: i64,
+    ) -> std::io::Result<Option<i32>> {
+        let partition_log = self.store.get(topic).unwrap().get(partition).unwrap();
+        let reads: Vec<(PathBuf, u64)> = partition_log
+            .segments
+            .values()
+            .filter(|segment| segment.max_timestamp >= timestamp)
+            .map(|segment| {
+                let relative_offset = lookup_relative_offset(&segment.time_index, timestamp);
+                let position = lookup_position(&segment.offset_index, relative_offset);
+                (segment.path.clone(), position)
+            })
+            .collect();
+        tokio::task::spawn_blocking(move || {
+            for (path, position) in reads {
+                let mut reader = SegmentReader::open_at(&path, position)?;
+                while let Some(record) = reader.next_record()? {
+                    if record.timestamp >= timestamp {
+                        return Ok(Some(record.offset as i32));
+                    }
+                }
+            }
+            Ok(None)
+        })
+        .await
+        .map_err(std::io::Error::other)?
+    }
+
+    fn partition_path(&self, topic: &str, partition: i32) -> std::io::Result<PathBuf> {
+        Ok(env::current_dir()?
             .join("logs")
             .join(topic)
-            .join(format!("{}", partition))
-            .join(format!("{}.log", file_with_data));
-        let f = File::open(file_path).unwrap();
-        let mut lines = BufReader::new(f).lines();
-        if let Some(line) = lines.nth(offset as usize) {
-            let s = line.unwrap();
-            return s.into_bytes();
+            .join(format!("{}", partition)))
+    }
+
+    /// Stores `offset` as the committed position of the consumer group in the
+    /// partition, the last offset its members consumed; it must be an offset
+    /// already written.
+    pub async fn commit_offset(
+        &mut self,
+        partition: &i32,
+        topic: &str,
+        group_id: &str,
+        offset: i32,
+    ) -> Result<(), StoreError> {
+        let partition_log = self.store.get(topic).unwrap().get(partition).unwrap();
+        if offset < 0 || partition_log.total_messages <= offset {
+            return Err(StoreError::OffsetOutOfRange {
+                offset,
+                log_start_offset: partition_log.log_start_offset,
+                high_watermark: partition_log.total_messages,
+            });
         }
-        return Vec::new();
+        let path = env::current_dir()?
+            .join("offsets")
+            .join(topic)
+            .join(group_id)
+            .join(format!("{}", partition));
+        self.write_to_file(path, offset).await?;
+        self.store
+            .get_mut(topic)
+            .unwrap()
+            .get_mut(partition)
+            .unwrap()
+            .committed_offsets
+            .insert(group_id.to_string(), offset);
+        Ok(())
     }
 
-    pub async fn commit_offset(&self, partition: &i32, topic: &str, offset: i32) -> Result<(), ()> {
-        let total_messages = self
-            .store
+    /// The offset the next record appended to the partition will get.
+    pub fn high_watermark(&self, partition: &i32, topic: &str) -> i32 {
+        self.store
             .get(topic)
             .unwrap()
             .get(partition)
             .unwrap()
-            .total_messages;
-        if total_messages <= offset {
-            return Err(());
-        }
-        let path = env::current_dir()
+            .total_messages
+    }
+
+    /// First offset still in the partition.
+    pub fn log_start_offset(&self, partition: &i32, topic: &str) -> i32 {
+        self.store
+            .get(topic)
             .unwrap()
-            .join("offsets")
-            .join(&topic)
-            .join(format!("{}", partition));
-        let res = self.write_to_file(path, offset).await;
-        if res.is_err() {
-            return Err(());
-        }
-        return Ok(());
+            .get(partition)
+            .unwrap()
+            .log_start_offset
+    }
+
+    pub fn get_committed_offset(
+        &self,
+        partition: &i32,
+        topic: &str,
+        group_id: &str,
+    ) -> Option<i32> {
+        self.store
+            .get(topic)?
+            .get(partition)?
+            .committed_offsets
+            .get(group_id)
+            .copied()
+    }
+
+    /// Where the group goes on reading the partition: at `position` when it has
+    /// one, after its committed offset otherwise, or else at the start of the log.
+    /// Kept within the records the log holds, since retention, compaction or a
+    /// truncated log may have removed the ones it points at.
+    pub fn read_position(
+        &self,
+        partition: &i32,
+        topic: &str,
+        group_id: &str,
+        position: Option<i32>,
+    ) -> i32 {
+        let log_start_offset = self.log_start_offset(partition, topic);
+        position
+            .or_else(|| {
+                self.get_committed_offset(partition, topic, group_id)
+                    .map(|committed| committed + 1)
+            })
+            .unwrap_or(log_start_offset)
+            .clamp(log_start_offset, self.high_watermark(partition, topic))
     }
 
     async fn write_to_file<P: AsRef<Path>>(&self, path: P, value: i32) -> std::io::Result<()> {
@@ -197,4 +888,16 @@ impl MessageStore {
         file.write_all(&bytes).await?;
         Ok(())
     }
+
+    async fn read_from_file<P: AsRef<Path>>(&self, path: P) -> std::io::Result<Option<i32>> {
+        let bytes = match fs::read(path).await {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        match bytes.try_into() {
+            Ok(bytes) => Ok(Some(i32::from_le_bytes(bytes))),
+            Err(_) => Ok(None),
+        }
+    }
 }
@@ -0,0 +1,27 @@
+use std::io;
+
+#[derive(Debug)]
+pub enum StoreError {
+    /// The offset is below `log_start_offset`, removed by retention or compaction,
+    /// or past `high_watermark`, the next offset to be written.
+    OffsetOutOfRange {
+        offset: i32,
+        log_start_offset: i32,
+        high_watermark: i32,
+    },
+    /// The record at or after `offset` failed its checksum or is malformed.
+    CorruptRecord {
+        offset: i32,
+    },
+    /// A producer batch was rejected before anything was written.
+    InvalidBatch {
+        reason: String,
+    },
+    Io(io::Error),
+}
+
+impl From<io::Error> for StoreError {
+    fn from(e: io::Error) -> Self {
+        StoreError::Io(e)
+    }
+}
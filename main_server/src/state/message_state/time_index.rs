@@ -0,0 +1,65 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+pub const TIME_INDEX_ENTRY_SIZE: usize = 12;
+
+/// One entry of a segment's sparse `.timeindex` file: `timestamp` is the largest
+/// record timestamp seen in the segment up to `base_offset + relative_offset`, the
+/// record that carries it. Stored as a big endian i64 followed by a big endian u32.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeIndexEntry {
+    pub timestamp: i64,
+    pub relative_offset: u32,
+}
+
+impl TimeIndexEntry {
+    pub fn encode(&self) -> [u8; TIME_INDEX_ENTRY_SIZE] {
+        let mut buffer = [0u8; TIME_INDEX_ENTRY_SIZE];
+        buffer[..8].copy_from_slice(&self.timestamp.to_be_bytes());
+        buffer[8..].copy_from_slice(&self.relative_offset.to_be_bytes());
+        buffer
+    }
+}
+
+/// Reads a time index file, `Ok(None)` if it does not exist or ends in a partial
+/// entry left by a crash, in which case it has to be rebuilt from the segment.
+pub fn read_time_index<P: AsRef<Path>>(path: P) -> io::Result<Option<Vec<TimeIndexEntry>>> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if bytes.len() % TIME_INDEX_ENTRY_SIZE != 0 {
+        return Ok(None);
+    }
+    let entries = bytes
+        .chunks_exact(TIME_INDEX_ENTRY_SIZE)
+        .map(|chunk| TimeIndexEntry {
+            timestamp: i64::from_be_bytes(chunk[..8].try_into().unwrap()),
+            relative_offset: u32::from_be_bytes(chunk[8..].try_into().unwrap()),
+        })
+        .collect();
+    Ok(Some(entries))
+}
+
+pub fn write_time_index<P: AsRef<Path>>(path: P, entries: &[TimeIndexEntry]) -> io::Result<()> {
+    let mut bytes = Vec::with_capacity(entries.len() * TIME_INDEX_ENTRY_SIZE);
+    for entry in entries {
+        bytes.extend_from_slice(&entry.encode());
+    }
+    fs::write(path, bytes)
+}
+
+/// Relative offset to start scanning from for the first record with a timestamp at
+/// or after `timestamp`. Every record before an entry is no newer than the entry's
+/// timestamp, so the last entry strictly older than `timestamp` is a safe start.
+pub fn lookup_relative_offset(entries: &[TimeIndexEntry], timestamp: i64) -> u32 {
+    let index = entries.partition_point(|entry| entry.timestamp < timestamp);
+    if index == 0 {
+        return 0;
+    }
+    entries[index - 1].relative_offset
+}
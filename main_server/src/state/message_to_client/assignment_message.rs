@@ -0,0 +1,57 @@
+use serde::Serialize;
+
+use crate::state::{
+    protocol::{
+        connection::ResponseWriter,
+        frame::{NO_ERROR, RequestHeader},
+    },
+    topic_state::topic_state::Assignment,
+};
+
+/// Reply to `JOINCONSUMER` and `DESCRIBEASSIGNMENT`: the partitions the consumer
+/// owns in the current generation of its group. Reading a partition starts after
+/// its committed offset, or at the start of the partition when there is none.
+#[derive(Serialize)]
+pub struct AssignmentMessage {
+    pub topic_name: String,
+    pub group_id: String,
+    pub generation: i32,
+    pub partitions: Vec<PartitionAssignment>,
+    pub revoking: Vec<i32>,
+    pub pending: Vec<i32>,
+}
+
+#[derive(Serialize)]
+pub struct PartitionAssignment {
+    pub partition: i32,
+    pub committed_offset: Option<i32>,
+}
+
+impl PartitionAssignment {
+    pub fn from_committed_offsets(committed_offsets: Vec<(i32, Option<i32>)>) -> Vec<Self> {
+        committed_offsets
+            .into_iter()
+            .map(|(partition, committed_offset)| Self {
+                partition,
+                committed_offset,
+            })
+            .collect()
+    }
+}
+
+impl AssignmentMessage {
+    pub fn new(topic_name: String, assignment: Assignment) -> Self {
+        Self {
+            topic_name,
+            group_id: assignment.group_id,
+            generation: assignment.generation,
+            partitions: PartitionAssignment::from_committed_offsets(assignment.partitions),
+            revoking: assignment.revoking,
+            pending: assignment.pending,
+        }
+    }
+
+    pub async fn send_message(&self, writer: &mut ResponseWriter, header: &RequestHeader) {
+        writer.send(header, NO_ERROR, self).await;
+    }
+}
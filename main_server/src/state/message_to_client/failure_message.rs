@@ -1,19 +1,29 @@
 use serde::Serialize;
-use tokio::{io::AsyncWriteExt, net::tcp::OwnedWriteHalf};
 
+use crate::state::protocol::{
+    broker_error::BrokerError, connection::ResponseWriter, frame::RequestHeader,
+};
+
+/// Reply to any request that failed. `error_code` is also sent in the binary frame
+/// header; `retriable` tells the client whether sending the same request again
+/// may succeed.
 #[derive(Serialize)]
-pub struct Failure {}
+pub struct Failure {
+    pub error_code: i16,
+    pub message: String,
+    pub retriable: bool,
+}
 
 impl Failure {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(error: &BrokerError) -> Self {
+        Self {
+            error_code: error.code(),
+            message: error.to_string(),
+            retriable: error.is_retriable(),
+        }
     }
 
-    pub async fn send_message(&self, write_half: &mut OwnedWriteHalf) {
-        let msg = Self {};
-        let mut vec = serde_json::to_vec(&msg).unwrap();
-        vec.push(b'\0');
-        let _ = write_half.write_all(&vec).await;
-        let _ = write_half.flush().await;
+    pub async fn send_message(&self, writer: &mut ResponseWriter, header: &RequestHeader) {
+        writer.send(header, self.error_code, self).await;
     }
 }
@@ -0,0 +1,36 @@
+use serde::Serialize;
+
+use crate::state::{
+    message_to_client::offset_message::OffsetMessage,
+    protocol::{
+        connection::ResponseWriter,
+        frame::{NO_ERROR, RequestHeader},
+    },
+    topic_state::topic_state::FetchedRecords,
+};
+
+/// Reply to `FETCH`: the records read, in offset order, and the partition's high
+/// watermark, the offset the next record appended to it will get. Fetching from
+/// `high_watermark` returns no records.
+#[derive(Serialize)]
+pub struct FetchMessage {
+    pub high_watermark: i32,
+    pub records: Vec<OffsetMessage>,
+}
+
+impl FetchMessage {
+    pub fn new(fetched: FetchedRecords) -> Self {
+        Self {
+            high_watermark: fetched.high_watermark,
+            records: fetched
+                .records
+                .into_iter()
+                .map(OffsetMessage::new)
+                .collect(),
+        }
+    }
+
+    pub async fn send_message(&self, writer: &mut ResponseWriter, header: &RequestHeader) {
+        writer.send(header, NO_ERROR, self).await;
+    }
+}
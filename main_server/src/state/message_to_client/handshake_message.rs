@@ -0,0 +1,24 @@
+use serde::Serialize;
+
+use crate::state::protocol::{
+    api_keys::ApiVersionRange,
+    connection::ResponseWriter,
+    frame::{NO_ERROR, RequestHeader},
+};
+
+/// Reply to the handshake: every request the broker serves and the versions of it
+/// the client may use.
+#[derive(Serialize)]
+pub struct HandshakeMessage {
+    pub api_versions: Vec<ApiVersionRange>,
+}
+
+impl HandshakeMessage {
+    pub fn new(api_versions: Vec<ApiVersionRange>) -> Self {
+        Self { api_versions }
+    }
+
+    pub async fn send_message(&self, writer: &mut ResponseWriter, header: &RequestHeader) {
+        writer.send(header, NO_ERROR, self).await;
+    }
+}
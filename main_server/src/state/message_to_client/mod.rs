@@ -1,3 +1,12 @@
+pub mod assignment_message;
 pub mod failure_message;
+pub mod fetch_message;
+pub mod handshake_message;
 pub mod offset_message;
+pub mod poll_message;
+pub mod produce_ack_message;
+pub mod produce_batch_message;
+pub mod push_message;
+pub mod rebalance_message;
 pub mod success_message;
+pub mod timestamp_offset_message;
@@ -1,23 +1,44 @@
 use serde::Serialize;
-use tokio::{io::AsyncWriteExt, net::tcp::OwnedWriteHalf};
 
+use crate::state::{
+    message_state::{compression::Codec, record::Record},
+    protocol::{
+        connection::ResponseWriter,
+        frame::{NO_ERROR, RequestHeader},
+    },
+};
+
+/// A record read back for a consumer. `offset` can be past the one asked for when
+/// compaction removed it, and `message` is `null` for a tombstone.
+///
+/// For a compressed batch `offset` is the batch's first offset, `key` is `null` and
+/// `message` holds the `record_count` records still compressed with `codec`.
 #[derive(Serialize)]
 pub struct OffsetMessage {
-    pub message: Vec<u8>,
+    pub offset: i32,
+    pub codec: Codec,
+    pub record_count: i32,
+    pub key: Option<Vec<u8>>,
+    pub message: Option<Vec<u8>>,
 }
 
 impl OffsetMessage {
-    pub fn new(msg: Vec<u8>) -> Self {
-        Self { message: msg }
+    pub fn new(record: Record) -> Self {
+        let record_count = record.record_count();
+        let message = match record.codec {
+            Codec::None => record.value,
+            _ => Some(record.compressed_entries().to_vec()),
+        };
+        Self {
+            offset: record.offset as i32,
+            codec: record.codec,
+            record_count,
+            key: record.key,
+            message,
+        }
     }
 
-    pub async fn send_message(&self, write_half: &mut OwnedWriteHalf) {
-        let msg = Self {
-            message: self.message.clone(),
-        };
-        let mut vec = serde_json::to_vec(&msg).unwrap();
-        vec.push(b'\0');
-        let _ = write_half.write_all(&vec).await;
-        let _ = write_half.flush().await;
+    pub async fn send_message(&self, writer: &mut ResponseWriter, header: &RequestHeader) {
+        writer.send(header, NO_ERROR, self).await;
     }
 }
@@ -0,0 +1,41 @@
+use serde::Serialize;
+
+use crate::state::{
+    message_state::record::Record,
+    message_to_client::offset_message::OffsetMessage,
+    protocol::{
+        connection::ResponseWriter,
+        frame::{NO_ERROR, RequestHeader},
+    },
+};
+
+/// Reply to `POLL`: the records read from every partition that had any, in the
+/// order the partitions were visited. No partitions means nothing new to read.
+#[derive(Serialize)]
+pub struct PollMessage {
+    pub partitions: Vec<PartitionRecords>,
+}
+
+#[derive(Serialize)]
+pub struct PartitionRecords {
+    pub partition: i32,
+    pub records: Vec<OffsetMessage>,
+}
+
+impl PollMessage {
+    pub fn new(polled: Vec<(i32, Vec<Record>)>) -> Self {
+        Self {
+            partitions: polled
+                .into_iter()
+                .map(|(partition, records)| PartitionRecords {
+                    partition,
+                    records: records.into_iter().map(OffsetMessage::new).collect(),
+                })
+                .collect(),
+        }
+    }
+
+    pub async fn send_message(&self, writer: &mut ResponseWriter, header: &RequestHeader) {
+        writer.send(header, NO_ERROR, self).await;
+    }
+}
@@ -0,0 +1,33 @@
+use serde::Serialize;
+
+use crate::state::{
+    protocol::{
+        connection::ResponseWriter,
+        frame::{NO_ERROR, RequestHeader},
+    },
+    topic_state::topic_state::ProducedRecord,
+};
+
+/// Reply to `MESSAGETOPIC` and `MESSAGEBATCH`: the partition the record went to, the
+/// offset it was given and its timestamp, as stored by the broker. For a batch,
+/// `offset` belongs to its first record and the others follow it.
+#[derive(Serialize)]
+pub struct ProduceAckMessage {
+    pub partition: i32,
+    pub offset: i32,
+    pub timestamp: i64,
+}
+
+impl ProduceAckMessage {
+    pub fn new(produced: ProducedRecord) -> Self {
+        Self {
+            partition: produced.partition,
+            offset: produced.offset,
+            timestamp: produced.timestamp,
+        }
+    }
+
+    pub async fn send_message(&self, writer: &mut ResponseWriter, header: &RequestHeader) {
+        writer.send(header, NO_ERROR, self).await;
+    }
+}
@@ -0,0 +1,72 @@
+use serde::Serialize;
+
+use crate::state::{
+    message_to_client::failure_message::Failure,
+    protocol::{
+        broker_error::BrokerError,
+        connection::ResponseWriter,
+        frame::{NO_ERROR, RequestHeader},
+    },
+    topic_state::topic_state::ProducedRecord,
+};
+
+/// Reply to `PRODUCEBATCH`, with the topics and partitions in the order of the
+/// request.
+#[derive(Serialize)]
+pub struct ProduceBatchMessage {
+    pub topics: Vec<TopicProduceResult>,
+}
+
+#[derive(Serialize)]
+pub struct TopicProduceResult {
+    pub topic_name: String,
+    pub partitions: Vec<PartitionProduceResult>,
+}
+
+/// Either `error` is set and none of the partition's records were appended, or
+/// `records` holds the offset and timestamp of every record in request order.
+#[derive(Serialize)]
+pub struct PartitionProduceResult {
+    pub partition: i32,
+    pub error: Option<Failure>,
+    pub records: Vec<RecordAck>,
+}
+
+#[derive(Serialize)]
+pub struct RecordAck {
+    pub offset: i32,
+    pub timestamp: i64,
+}
+
+impl PartitionProduceResult {
+    pub fn new(partition: i32, result: Result<Vec<ProducedRecord>, BrokerError>) -> Self {
+        match result {
+            Ok(produced) => Self {
+                partition,
+                error: None,
+                records: produced
+                    .into_iter()
+                    .map(|produced| RecordAck {
+                        offset: produced.offset,
+                        timestamp: produced.timestamp,
+                    })
+                    .collect(),
+            },
+            Err(e) => Self {
+                partition,
+                error: Some(Failure::new(&e)),
+                records: Vec::new(),
+            },
+        }
+    }
+}
+
+impl ProduceBatchMessage {
+    pub fn new(topics: Vec<TopicProduceResult>) -> Self {
+        Self { topics }
+    }
+
+    pub async fn send_message(&self, writer: &mut ResponseWriter, header: &RequestHeader) {
+        writer.send(header, NO_ERROR, self).await;
+    }
+}
@@ -0,0 +1,32 @@
+use serde::Serialize;
+
+use crate::state::{
+    message_to_client::offset_message::OffsetMessage,
+    protocol::{
+        connection::ResponseWriter,
+        frame::{NO_ERROR, RequestHeader},
+    },
+};
+
+/// Records streamed to a subscribed consumer, sent with the header of its
+/// `SUBSCRIBE` request. Every record uses up one credit.
+#[derive(Serialize)]
+pub struct PushMessage {
+    pub topic_name: String,
+    pub partition: i32,
+    pub records: Vec<OffsetMessage>,
+}
+
+impl PushMessage {
+    pub fn new(topic_name: String, partition: i32, records: Vec<OffsetMessage>) -> Self {
+        Self {
+            topic_name,
+            partition,
+            records,
+        }
+    }
+
+    pub async fn send_message(&self, writer: &mut ResponseWriter, header: &RequestHeader) {
+        writer.send(header, NO_ERROR, self).await;
+    }
+}
@@ -0,0 +1,43 @@
+use serde::Serialize;
+
+use crate::state::{
+    message_to_client::assignment_message::PartitionAssignment,
+    protocol::{
+        connection::ResponseWriter,
+        frame::{NO_ERROR, RequestHeader},
+    },
+    topic_state::consumer_group::RebalanceEvent,
+};
+
+/// Pushed to a group member, with the header of its `JOINCONSUMER` request, when
+/// a rebalance or another member giving up partitions changed its partitions.
+/// Commits have to carry `generation` from then on. `partitions` is everything
+/// the member owns after the change, with the group's committed offsets.
+#[derive(Serialize)]
+pub struct RebalanceMessage {
+    pub topic_name: String,
+    pub generation: i32,
+    pub assigned: Vec<i32>,
+    pub revoked: Vec<i32>,
+    pub partitions: Vec<PartitionAssignment>,
+}
+
+impl RebalanceMessage {
+    pub fn new(
+        topic_name: String,
+        event: RebalanceEvent,
+        committed_offsets: Vec<(i32, Option<i32>)>,
+    ) -> Self {
+        Self {
+            topic_name,
+            generation: event.generation,
+            assigned: event.assigned,
+            revoked: event.revoked,
+            partitions: PartitionAssignment::from_committed_offsets(committed_offsets),
+        }
+    }
+
+    pub async fn send_message(&self, writer: &mut ResponseWriter, header: &RequestHeader) {
+        writer.send(header, NO_ERROR, self).await;
+    }
+}
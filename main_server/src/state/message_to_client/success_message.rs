@@ -1,7 +1,11 @@
 use serde::Serialize;
-use tokio::{io::AsyncWriteExt, net::tcp::OwnedWriteHalf};
 
-#[derive(Serialize)]
+use crate::state::protocol::{
+    connection::ResponseWriter,
+    frame::{NO_ERROR, RequestHeader},
+};
+
+#[derive(Serialize, Default)]
 pub struct Success {}
 
 impl Success {
@@ -9,11 +13,7 @@ impl Success {
         Self {}
     }
 
-    pub async fn send_message(&self, write_half: &mut OwnedWriteHalf) {
-        let msg = Self {};
-        let mut vec = serde_json::to_vec(&msg).unwrap();
-        vec.push(b'\0');
-        let _ = write_half.write_all(&vec).await;
-        let _ = write_half.flush().await;
+    pub async fn send_message(&self, writer: &mut ResponseWriter, header: &RequestHeader) {
+        writer.send(header, NO_ERROR, self).await;
     }
 }
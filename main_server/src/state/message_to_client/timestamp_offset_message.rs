@@ -0,0 +1,22 @@
+use serde::Serialize;
+
+use crate::state::protocol::{
+    connection::ResponseWriter,
+    frame::{NO_ERROR, RequestHeader},
+};
+
+/// Reply to `OFFSETFORTIMESTAMP`; `offset` is null when no record is that recent.
+#[derive(Serialize)]
+pub struct TimestampOffsetMessage {
+    pub offset: Option<i32>,
+}
+
+impl TimestampOffsetMessage {
+    pub fn new(offset: Option<i32>) -> Self {
+        Self { offset }
+    }
+
+    pub async fn send_message(&self, writer: &mut ResponseWriter, header: &RequestHeader) {
+        writer.send(header, NO_ERROR, self).await;
+    }
+}
@@ -1,7 +1,9 @@
+pub mod config;
 pub mod consumer;
 pub mod helpers;
 pub mod message_from_client;
 pub mod message_state;
 pub mod message_to_client;
 pub mod producer;
+pub mod protocol;
 pub mod topic_state;
@@ -1,17 +1,25 @@
 use std::sync::Arc;
 
-use tokio::{
-    io::{AsyncBufReadExt, BufReader},
-    net::tcp::{OwnedReadHalf, OwnedWriteHalf},
-    sync::RwLock,
-};
+use tokio::sync::RwLock;
 
 use crate::state::{
     helpers::helper::Helper,
     message_from_client::message_for_producer::message::{
-        CreateTopic, DeleteTopic, MessageTopic, ProducerMessage,
+        CreateTopic, DeleteTopic, Message, MessageBatch, MessageTopic, ProduceBatch,
+    },
+    message_state::segment::PendingSync,
+    message_to_client::{
+        failure_message::Failure,
+        produce_ack_message::ProduceAckMessage,
+        produce_batch_message::{PartitionProduceResult, ProduceBatchMessage, TopicProduceResult},
+        success_message::Success,
+    },
+    protocol::{
+        broker_error::BrokerError,
+        connection::{Request, RequestReader, ResponseWriter},
+        frame::RequestHeader,
+        ordered_executor::OrderedExecutor,
     },
-    message_to_client::{failure_message::Failure, success_message::Success},
     topic_state::topic_state::Topic,
 };
 
@@ -31,51 +39,35 @@ impl Producer {
         }
     }
 
-    pub async fn handler(&self, mut reader: BufReader<OwnedReadHalf>, mut writer: OwnedWriteHalf) {
-        let mut buffer = Vec::new();
+    /// Reads requests until the client disconnects, without waiting for earlier
+    /// ones to finish. Requests on the same topic are handled in the order they
+    /// were sent, so records keep their order within every partition; a
+    /// `PRODUCEBATCH` is ordered against the requests on each of its topics.
+    pub async fn handler(self: Arc<Self>, mut reader: RequestReader, writer: ResponseWriter) {
+        let mut executor = OrderedExecutor::new();
         loop {
-            buffer.clear();
-            match reader.read_until(b'\0', &mut buffer).await {
-                Ok(0) => {
+            match reader.next_request::<Message>().await {
+                Ok(None) => {
                     println!("disconnect");
                     return;
                 }
-                Ok(n) => {
-                    let message = serde_json::from_slice::<ProducerMessage>(&buffer[..n - 1]);
-                    match message {
-                        Err(_) => {
-                            Failure::new().send_message(&mut writer).await;
-                            continue;
+                Ok(Some(Request { header, body })) => {
+                    let mut writer = writer.clone();
+                    match body {
+                        Err(error) => {
+                            Failure::new(&error)
+                                .send_message(&mut writer, &header)
+                                .await;
+                        }
+                        Ok(message) => {
+                            let ordering_keys = Self::ordering_keys(&message);
+                            let producer = Arc::clone(&self);
+                            executor
+                                .spawn(ordering_keys, async move {
+                                    producer.handle_request(header, message, writer).await;
+                                })
+                                .await;
                         }
-                        Ok(msg) => match msg.message {
-                            crate::state::message_from_client::message_for_producer::message::Message::CREATETOPIC(message) => {
-                                let CreateTopic { topic_name, partitions } = message;
-                                {
-                                    let mut topics_guard = self.topics_data.write().await;
-                                    topics_guard.add_topic(topic_name, partitions).await;
-                                }
-                                let success_message = Success::new();
-                                success_message.send_message(&mut writer).await;
-                            },
-                            crate::state::message_from_client::message_for_producer::message::Message::DELETETOPIC(message) => {
-                                let DeleteTopic { topic_name } = message;
-                                {
-                                    let mut topics_guard = self.topics_data.write().await;
-                                    topics_guard.delete_topic(&topic_name).await;
-                                }
-                                let success_message = Success::new();
-                                success_message.send_message(&mut writer).await;
-                            },
-                            crate::state::message_from_client::message_for_producer::message::Message::MESSAGETOPIC(message) => {
-                                let MessageTopic { topic_name, data, key } = message;
-                                {
-                                    let mut topics_guard = self.topics_data.write().await;
-                                    topics_guard.send_message(key, data, topic_name).await;
-                                }
-                                let success_message = Success::new();
-                                success_message.send_message(&mut writer).await;
-                            }
-                        },
                     }
                 }
                 Err(e) => {
@@ -85,4 +77,122 @@ impl Producer {
             };
         }
     }
+
+    fn ordering_keys(message: &Message) -> Vec<String> {
+        let topic_name = match message {
+            Message::CREATETOPIC(message) => &message.topic_name,
+            Message::DELETETOPIC(message) => &message.topic_name,
+            Message::MESSAGETOPIC(message) => &message.topic_name,
+            Message::MESSAGEBATCH(message) => &message.topic_name,
+            Message::PRODUCEBATCH(message) => {
+                return message
+                    .topics
+                    .iter()
+                    .map(|topic_records| topic_records.topic_name.clone())
+                    .collect();
+            }
+        };
+        vec![topic_name.clone()]
+    }
+
+    /// Waits for the sync owed to an append, which is only acknowledged once it is
+    /// done. Called with the store unlocked.
+    async fn synced<T>(res: Result<(T, PendingSync), BrokerError>) -> Result<T, BrokerError> {
+        let (produced, sync) = res?;
+        if let Err(e) = sync.wait().await {
+            eprintln!("Failed to fsync appended records; err = {:?}", e);
+            return Err(e.into());
+        }
+        Ok(produced)
+    }
+
+    async fn handle_request(
+        &self,
+        header: RequestHeader,
+        message: Message,
+        mut writer: ResponseWriter,
+    ) {
+        match message {
+                crate::state::message_from_client::message_for_producer::message::Message::CREATETOPIC(message) => {
+                    let CreateTopic { topic_name, partitions, config } = message;
+                    let res;
+                    {
+                        let mut topics_guard = self.topics_data.write().await;
+                        res = topics_guard.add_topic(topic_name, partitions, config).await;
+                    }
+                    match res {
+                        Ok(()) => Success::new().send_message(&mut writer, &header).await,
+                        Err(e) => Failure::new(&e).send_message(&mut writer, &header).await,
+                    }
+                },
+                crate::state::message_from_client::message_for_producer::message::Message::DELETETOPIC(message) => {
+                    let DeleteTopic { topic_name } = message;
+                    let res;
+                    {
+                        let mut topics_guard = self.topics_data.write().await;
+                        res = topics_guard.delete_topic(&topic_name).await;
+                    }
+                    match res {
+                        Ok(()) => Success::new().send_message(&mut writer, &header).await,
+                        Err(e) => Failure::new(&e).send_message(&mut writer, &header).await,
+                    }
+                },
+                crate::state::message_from_client::message_for_producer::message::Message::MESSAGETOPIC(message) => {
+                    let MessageTopic { topic_name, data, key, timestamp } = message;
+                    let res;
+                    {
+                        let mut topics_guard = self.topics_data.write().await;
+                        res = topics_guard.send_message(key, data, topic_name, timestamp).await;
+                    }
+                    let res = Self::synced(res).await;
+                    match res {
+                        Ok(produced) => ProduceAckMessage::new(produced).send_message(&mut writer, &header).await,
+                        Err(e) => Failure::new(&e).send_message(&mut writer, &header).await,
+                    }
+                }
+                crate::state::message_from_client::message_for_producer::message::Message::MESSAGEBATCH(batch) => {
+                    let MessageBatch { key, topic_name, codec, record_count, records, timestamp } = batch;
+                    let res;
+                    {
+                        let mut topics_guard = self.topics_data.write().await;
+                        res = topics_guard.send_batch(key, topic_name, codec, record_count, records, timestamp).await;
+                    }
+                    let res = Self::synced(res).await;
+                    match res {
+                        Ok(produced) => ProduceAckMessage::new(produced).send_message(&mut writer, &header).await,
+                        Err(e) => Failure::new(&e).send_message(&mut writer, &header).await,
+                    }
+                }
+                crate::state::message_from_client::message_for_producer::message::Message::PRODUCEBATCH(batch) => {
+                    let ProduceBatch { topics } = batch;
+                    let mut appended = Vec::with_capacity(topics.len());
+                    {
+                        let mut topics_guard = self.topics_data.write().await;
+                        for topic_records in topics {
+                            let mut partitions = Vec::with_capacity(topic_records.partitions.len());
+                            for partition_records in topic_records.partitions {
+                                let res = topics_guard
+                                    .send_records(&topic_records.topic_name, partition_records.partition, partition_records.records)
+                                    .await;
+                                partitions.push((partition_records.partition, res));
+                            }
+                            appended.push((topic_records.topic_name, partitions));
+                        }
+                    }
+                    let mut results = Vec::with_capacity(appended.len());
+                    for (topic_name, partitions) in appended {
+                        let mut partition_results = Vec::with_capacity(partitions.len());
+                        for (partition, res) in partitions {
+                            let res = Self::synced(res).await;
+                            partition_results.push(PartitionProduceResult::new(partition, res));
+                        }
+                        results.push(TopicProduceResult {
+                            topic_name,
+                            partitions: partition_results,
+                        });
+                    }
+                    ProduceBatchMessage::new(results).send_message(&mut writer, &header).await;
+                }
+        }
+    }
 }
@@ -0,0 +1,97 @@
+use serde::Serialize;
+
+pub const HANDSHAKE: i16 = 0;
+pub const CREATE_TOPIC: i16 = 1;
+pub const DELETE_TOPIC: i16 = 2;
+pub const MESSAGE_TOPIC: i16 = 3;
+pub const MESSAGE_BATCH: i16 = 4;
+pub const JOIN_CONSUMER: i16 = 5;
+pub const LEAVE_CONSUMER: i16 = 6;
+pub const GET_OFFSET_MESSAGE: i16 = 7;
+pub const COMMIT_OFFSET: i16 = 8;
+pub const OFFSET_FOR_TIMESTAMP: i16 = 9;
+pub const PRODUCE_BATCH: i16 = 10;
+pub const FETCH: i16 = 11;
+pub const SUBSCRIBE: i16 = 12;
+pub const CREDIT: i16 = 13;
+pub const POLL: i16 = 14;
+pub const HEARTBEAT: i16 = 15;
+pub const REVOKE_PARTITIONS: i16 = 16;
+pub const DESCRIBE_ASSIGNMENT: i16 = 17;
+
+/// Versions of one request the broker can serve, advertised in the handshake.
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct ApiVersionRange {
+    pub api_key: i16,
+    pub min_version: i16,
+    pub max_version: i16,
+}
+
+/// Every request the broker understands: its api key, the name of its variant in
+/// the producer or consumer message enum, and the versions it supports.
+const APIS: &[(i16, &str, i16, i16)] = &[
+    (HANDSHAKE, "HANDSHAKE", 0, 0),
+    (CREATE_TOPIC, "CREATETOPIC", 0, 0),
+    (DELETE_TOPIC, "DELETETOPIC", 0, 0),
+    (MESSAGE_TOPIC, "MESSAGETOPIC", 0, 0),
+    (MESSAGE_BATCH, "MESSAGEBATCH", 0, 0),
+    (JOIN_CONSUMER, "JOINCONSUMER", 0, 3),
+    (LEAVE_CONSUMER, "LEAVECONSUMER", 0, 0),
+    (GET_OFFSET_MESSAGE, "GETOFFSETMESSAGE", 0, 0),
+    (COMMIT_OFFSET, "COMMITOFFSET", 0, 1),
+    (OFFSET_FOR_TIMESTAMP, "OFFSETFORTIMESTAMP", 0, 0),
+    (PRODUCE_BATCH, "PRODUCEBATCH", 0, 0),
+    (FETCH, "FETCH", 0, 1),
+    (SUBSCRIBE, "SUBSCRIBE", 0, 0),
+    (CREDIT, "CREDIT", 0, 0),
+    (POLL, "POLL", 0, 0),
+    (HEARTBEAT, "HEARTBEAT", 0, 0),
+    (REVOKE_PARTITIONS, "REVOKEPARTITIONS", 0, 0),
+    (DESCRIBE_ASSIGNMENT, "DESCRIBEASSIGNMENT", 0, 0),
+];
+
+/// Number of fields the request struct has in every version of the requests whose
+/// layout grew. Fields are only ever appended, with a `#[serde(default)]` that
+/// older versions get.
+const REQUEST_FIELDS: &[(i16, i16, usize)] = &[
+    // v1 added `group_id`, v2 `session_timeout_ms` and `max_poll_interval_ms`,
+    // v3 `assignment_strategy`.
+    (JOIN_CONSUMER, 0, 1),
+    (JOIN_CONSUMER, 1, 2),
+    (JOIN_CONSUMER, 2, 4),
+    (JOIN_CONSUMER, 3, 5),
+    // v1 added `generation`.
+    (COMMIT_OFFSET, 0, 3),
+    (COMMIT_OFFSET, 1, 4),
+    // v1 added `max_wait_ms`.
+    (FETCH, 0, 7),
+    (FETCH, 1, 8),
+];
+
+pub fn request_fields(api_key: i16, api_version: i16) -> Option<usize> {
+    REQUEST_FIELDS
+        .iter()
+        .find(|(key, version, _)| *key == api_key && *version == api_version)
+        .map(|(.., fields)| *fields)
+}
+
+pub fn request_name(api_key: i16) -> Option<&'static str> {
+    APIS.iter()
+        .find(|(key, ..)| *key == api_key)
+        .map(|(_, name, ..)| *name)
+}
+
+pub fn is_supported(api_key: i16, api_version: i16) -> bool {
+    APIS.iter()
+        .any(|(key, _, min, max)| *key == api_key && (*min..=*max).contains(&api_version))
+}
+
+pub fn supported_versions() -> Vec<ApiVersionRange> {
+    APIS.iter()
+        .map(|(api_key, _, min_version, max_version)| ApiVersionRange {
+            api_key: *api_key,
+            min_version: *min_version,
+            max_version: *max_version,
+        })
+        .collect()
+}
@@ -0,0 +1,179 @@
+use std::{fmt, io};
+
+use crate::state::message_state::store_error::StoreError;
+
+/// Everything a request can fail with. Every variant has a stable error code, sent
+/// in the response frame header and in the `Failure` body, and says whether the
+/// same request may succeed when retried as is. Code 8 belonged to a removed
+/// variant and is not reused.
+#[derive(Debug)]
+pub enum BrokerError {
+    /// The record at or after `offset` failed its checksum or is malformed.
+    CorruptRecord {
+        offset: i32,
+    },
+    UnsupportedVersion {
+        api_key: i16,
+        api_version: i16,
+    },
+    /// The request could not be decoded or its fields make no sense.
+    InvalidRequest {
+        reason: String,
+    },
+    UnknownTopic {
+        topic: String,
+    },
+    UnknownPartition {
+        topic: String,
+        partition: i32,
+    },
+    /// `offset` is below the start of the log or past its end.
+    OffsetOutOfRange {
+        offset: i32,
+        log_start_offset: i32,
+        high_watermark: i32,
+    },
+    /// Nothing has been written at `offset` yet.
+    OffsetNotAvailable {
+        offset: i32,
+        high_watermark: i32,
+    },
+    TopicAlreadyExists {
+        topic: String,
+    },
+    /// The consumer has not joined the topic.
+    UnknownConsumer {
+        topic: String,
+    },
+    /// Reading or writing the log or the committed offsets failed.
+    Storage(io::Error),
+    /// The consumer acted on a generation of its group that a rebalance replaced.
+    IllegalGeneration {
+        group_id: String,
+        generation: i32,
+        current_generation: i32,
+    },
+    /// The consumer does not own the partition in the current generation.
+    PartitionNotAssigned {
+        topic: String,
+        partition: i32,
+    },
+}
+
+impl BrokerError {
+    pub fn code(&self) -> i16 {
+        match self {
+            BrokerError::CorruptRecord { .. } => 1,
+            BrokerError::UnsupportedVersion { .. } => 2,
+            BrokerError::InvalidRequest { .. } => 3,
+            BrokerError::UnknownTopic { .. } => 4,
+            BrokerError::UnknownPartition { .. } => 5,
+            BrokerError::OffsetOutOfRange { .. } => 6,
+            BrokerError::OffsetNotAvailable { .. } => 7,
+            BrokerError::TopicAlreadyExists { .. } => 9,
+            BrokerError::UnknownConsumer { .. } => 10,
+            BrokerError::Storage(_) => 11,
+            BrokerError::IllegalGeneration { .. } => 12,
+            BrokerError::PartitionNotAssigned { .. } => 13,
+        }
+    }
+
+    pub fn is_retriable(&self) -> bool {
+        matches!(
+            self,
+            BrokerError::OffsetNotAvailable { .. } | BrokerError::Storage(_)
+        )
+    }
+
+    pub fn invalid_request(reason: impl Into<String>) -> Self {
+        BrokerError::InvalidRequest {
+            reason: reason.into(),
+        }
+    }
+}
+
+impl fmt::Display for BrokerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BrokerError::CorruptRecord { offset } => {
+                write!(f, "corrupted record at offset {}", offset)
+            }
+            BrokerError::UnsupportedVersion {
+                api_key,
+                api_version,
+            } => write!(
+                f,
+                "version {} of api {} is not supported",
+                api_version, api_key
+            ),
+            BrokerError::InvalidRequest { reason } => write!(f, "invalid request: {}", reason),
+            BrokerError::UnknownTopic { topic } => write!(f, "unknown topic {}", topic),
+            BrokerError::UnknownPartition { topic, partition } => {
+                write!(f, "unknown partition {} of topic {}", partition, topic)
+            }
+            BrokerError::OffsetOutOfRange {
+                offset,
+                log_start_offset,
+                high_watermark,
+            } => write!(
+                f,
+                "offset {} is outside of the log, which starts at {} and ends before {}",
+                offset, log_start_offset, high_watermark
+            ),
+            BrokerError::OffsetNotAvailable {
+                offset,
+                high_watermark,
+            } => write!(
+                f,
+                "offset {} has not been written yet, the next offset is {}",
+                offset, high_watermark
+            ),
+            BrokerError::TopicAlreadyExists { topic } => {
+                write!(f, "topic {} already exists", topic)
+            }
+            BrokerError::UnknownConsumer { topic } => {
+                write!(f, "not a consumer of topic {}", topic)
+            }
+            BrokerError::Storage(e) => write!(f, "storage error: {}", e),
+            BrokerError::IllegalGeneration {
+                group_id,
+                generation,
+                current_generation,
+            } => write!(
+                f,
+                "generation {} of group {} is stale, the current generation is {}",
+                generation, group_id, current_generation
+            ),
+            BrokerError::PartitionNotAssigned { topic, partition } => write!(
+                f,
+                "partition {} of topic {} is not assigned to the consumer",
+                partition, topic
+            ),
+        }
+    }
+}
+
+impl From<io::Error> for BrokerError {
+    fn from(e: io::Error) -> Self {
+        BrokerError::Storage(e)
+    }
+}
+
+impl From<StoreError> for BrokerError {
+    fn from(e: StoreError) -> Self {
+        match e {
+            StoreError::OffsetOutOfRange {
+                offset,
+                log_start_offset,
+                high_watermark,
+            } => BrokerError::OffsetOutOfRange {
+                offset,
+                log_start_offset,
+                high_watermark,
+            },
+            StoreError::CorruptRecord { offset } => BrokerError::CorruptRecord { offset },
+            StoreError::InvalidBatch { reason } => BrokerError::InvalidRequest { reason },
+            StoreError::Io(e) => BrokerError::Storage(e),
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use std::{
+    io::{self, ErrorKind},
+    sync::Arc,
+};
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::tcp::{OwnedReadHalf, OwnedWriteHalf},
+    sync::Mutex,
+};
+
+use crate::state::{
+    config::broker_config::WireFormat,
+    protocol::{
+        api_keys::{is_supported, request_fields, request_name},
+        binary_codec,
+        broker_error::BrokerError,
+        frame::{MAX_FRAME_SIZE, REQUEST_HEADER_SIZE, RequestHeader, encode_response},
+    },
+};
+
+/// A request read off a connection. `body` holds the error to answer with when the
+/// request could not be decoded.
+pub struct Request<T> {
+    pub header: RequestHeader,
+    pub body: Result<T, BrokerError>,
+}
+
+/// Envelope of a request in JSON mode, where there is no frame header.
+#[derive(Deserialize)]
+struct JsonRequest<T> {
+    message: T,
+    #[serde(default)]
+    correlation_id: i32,
+}
+
+pub struct RequestReader {
+    reader: BufReader<OwnedReadHalf>,
+    wire_format: WireFormat,
+}
+
+impl RequestReader {
+    pub fn new(read_half: OwnedReadHalf, wire_format: WireFormat) -> Self {
+        Self {
+            reader: BufReader::new(read_half),
+            wire_format,
+        }
+    }
+
+    /// Reads the next request, `Ok(None)` once the client closed the connection.
+    pub async fn next_request<T: DeserializeOwned>(&mut self) -> io::Result<Option<Request<T>>> {
+        match self.wire_format {
+            WireFormat::Binary => self.next_binary_request().await,
+            WireFormat::Json => self.next_json_request().await,
+        }
+    }
+
+    async fn next_binary_request<T: DeserializeOwned>(&mut self) -> io::Result<Option<Request<T>>> {
+        let mut length = [0u8; 4];
+        match self.reader.read_exact(&mut length).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let length = i32::from_be_bytes(length);
+        if length < REQUEST_HEADER_SIZE as i32 || length as usize > MAX_FRAME_SIZE {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid frame length {}", length),
+            ));
+        }
+        let mut frame = vec![0u8; length as usize];
+        self.reader.read_exact(&mut frame).await?;
+        let (header, payload) = frame.split_at(REQUEST_HEADER_SIZE);
+        let header = RequestHeader::decode(header.try_into().unwrap());
+        let Some(name) = request_name(header.api_key) else {
+            eprintln!("Unknown api key {}", header.api_key);
+            return Ok(Some(Request {
+                header,
+                body: Err(BrokerError::invalid_request(format!(
+                    "unknown api key {}",
+                    header.api_key
+                ))),
+            }));
+        };
+        if !is_supported(header.api_key, header.api_version) {
+            return Ok(Some(Request {
+                header,
+                body: Err(BrokerError::UnsupportedVersion {
+                    api_key: header.api_key,
+                    api_version: header.api_version,
+                }),
+            }));
+        }
+        let fields = request_fields(header.api_key, header.api_version);
+        let body = binary_codec::from_bytes::<T>(payload, name, fields).map_err(|e| {
+            eprintln!("Invalid {} request; err = {}", name, e);
+            BrokerError::invalid_request(e.to_string())
+        });
+        Ok(Some(Request { header, body }))
+    }
+
+    async fn next_json_request<T: DeserializeOwned>(&mut self) -> io::Result<Option<Request<T>>> {
+        let mut buffer = Vec::new();
+        let n = self.reader.read_until(b'\0', &mut buffer).await?;
+        if n == 0 || buffer[n - 1] != b'\0' {
+            return Ok(None);
+        }
+        let mut header = RequestHeader {
+            api_key: -1,
+            api_version: 0,
+            correlation_id: 0,
+        };
+        let body = match serde_json::from_slice::<JsonRequest<T>>(&buffer[..n - 1]) {
+            Ok(request) => {
+                header.correlation_id = request.correlation_id;
+                Ok(request.message)
+            }
+            Err(e) => {
+                eprintln!("Invalid request; err = {}", e);
+                // Still echo the correlation id if the envelope is readable.
+                if let Ok(serde_json::Value::Object(envelope)) =
+                    serde_json::from_slice(&buffer[..n - 1])
+                    && let Some(correlation_id) = envelope
+                        .get("correlation_id")
+                        .and_then(|correlation_id| correlation_id.as_i64())
+                {
+                    header.correlation_id = correlation_id as i32;
+                }
+                Err(BrokerError::invalid_request(e.to_string()))
+            }
+        };
+        Ok(Some(Request { header, body }))
+    }
+}
+
+/// Sends responses on a connection. Clones share the socket, so requests handled
+/// concurrently can each answer as soon as they are done; every response is
+/// written whole before the next one starts.
+#[derive(Clone)]
+pub struct ResponseWriter {
+    write_half: Arc<Mutex<OwnedWriteHalf>>,
+    wire_format: WireFormat,
+}
+
+impl ResponseWriter {
+    pub fn new(write_half: OwnedWriteHalf, wire_format: WireFormat) -> Self {
+        Self {
+            write_half: Arc::new(Mutex::new(write_half)),
+            wire_format,
+        }
+    }
+
+    /// Answers the request `header` belongs to. JSON mode sends `body` with the
+    /// request's `correlation_id` added, followed by a NUL byte.
+    pub async fn send<T: Serialize>(&mut self, header: &RequestHeader, error_code: i16, body: &T) {
+        let bytes = match self.wire_format {
+            WireFormat::Binary => {
+                encode_response(header, error_code, &binary_codec::to_bytes(body).unwrap())
+            }
+            WireFormat::Json => {
+                let mut value = serde_json::to_value(body).unwrap();
+                if let serde_json::Value::Object(fields) = &mut value {
+                    fields.insert("correlation_id".to_string(), header.correlation_id.into());
+                }
+                let mut vec = serde_json::to_vec(&value).unwrap();
+                vec.push(b'\0');
+                vec
+            }
+        };
+        let mut write_half = self.write_half.lock().await;
+        let _ = write_half.write_all(&bytes).await;
+        let _ = write_half.flush().await;
+    }
+}
@@ -0,0 +1,43 @@
+/// Largest request the broker accepts; a longer length prefix closes the connection.
+pub const MAX_FRAME_SIZE: usize = 64 * 1024 * 1024;
+
+/// Bytes of a request header after the length prefix: api key, api version and
+/// correlation id.
+pub const REQUEST_HEADER_SIZE: usize = 2 + 2 + 4;
+
+/// Error code of a successful response; failures use `BrokerError::code`.
+pub const NO_ERROR: i16 = 0;
+
+/// Header in front of every binary request, echoed back in its response.
+///
+/// Request frame: `length: i32 | api_key: i16 | api_version: i16 |
+/// correlation_id: i32 | payload`; response frame: the same followed by an
+/// `error_code: i16` before the payload. `length` counts every byte after itself.
+#[derive(Debug, Clone, Copy)]
+pub struct RequestHeader {
+    pub api_key: i16,
+    pub api_version: i16,
+    pub correlation_id: i32,
+}
+
+impl RequestHeader {
+    pub fn decode(bytes: &[u8; REQUEST_HEADER_SIZE]) -> Self {
+        Self {
+            api_key: i16::from_be_bytes(bytes[..2].try_into().unwrap()),
+            api_version: i16::from_be_bytes(bytes[2..4].try_into().unwrap()),
+            correlation_id: i32::from_be_bytes(bytes[4..].try_into().unwrap()),
+        }
+    }
+}
+
+pub fn encode_response(header: &RequestHeader, error_code: i16, payload: &[u8]) -> Vec<u8> {
+    let length = REQUEST_HEADER_SIZE + 2 + payload.len();
+    let mut buffer = Vec::with_capacity(4 + length);
+    buffer.extend_from_slice(&(length as i32).to_be_bytes());
+    buffer.extend_from_slice(&header.api_key.to_be_bytes());
+    buffer.extend_from_slice(&header.api_version.to_be_bytes());
+    buffer.extend_from_slice(&header.correlation_id.to_be_bytes());
+    buffer.extend_from_slice(&error_code.to_be_bytes());
+    buffer.extend_from_slice(payload);
+    buffer
+}
@@ -0,0 +1,6 @@
+pub mod api_keys;
+pub mod binary_codec;
+pub mod broker_error;
+pub mod connection;
+pub mod frame;
+pub mod ordered_executor;
@@ -0,0 +1,73 @@
+use std::{collections::HashMap, future::Future, sync::Arc};
+
+use tokio::sync::{Semaphore, watch};
+
+/// Requests of one connection that may be queued or running at the same time.
+pub const MAX_IN_FLIGHT_REQUESTS: usize = 256;
+
+/// Runs the requests of one connection concurrently, except that requests sharing
+/// an ordering key run one after another in the order they arrived. A request
+/// with several keys waits for the earlier requests on every one of them, and the
+/// later requests on any of them wait for it.
+pub struct OrderedExecutor {
+    /// For every ordering key, a receiver whose sender the last request spawned
+    /// with that key drops once it has finished.
+    tails: HashMap<String, watch::Receiver<()>>,
+    in_flight: Arc<Semaphore>,
+}
+
+impl Default for OrderedExecutor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OrderedExecutor {
+    pub fn new() -> Self {
+        Self {
+            tails: HashMap::new(),
+            in_flight: Arc::new(Semaphore::new(MAX_IN_FLIGHT_REQUESTS)),
+        }
+    }
+
+    /// Waits while `MAX_IN_FLIGHT_REQUESTS` requests are in flight, so that a
+    /// client sending faster than it is served stops being read from.
+    pub async fn spawn<K, F>(&mut self, ordering_keys: K, task: F)
+    where
+        K: IntoIterator<Item = String>,
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let permit = Arc::clone(&self.in_flight)
+            .acquire_owned()
+            .await
+            .expect("the in-flight semaphore is never closed");
+        // Keys whose last request has finished have nothing left to order.
+        self.tails.retain(|_, tail| tail.has_changed().is_ok());
+        let (finished, tail) = watch::channel(());
+        let mut predecessors = Vec::new();
+        for ordering_key in ordering_keys {
+            if let Some(predecessor) = self.tails.insert(ordering_key, tail.clone())
+                && !predecessor.same_channel(&tail)
+            {
+                predecessors.push(predecessor);
+            }
+        }
+        tokio::spawn(async move {
+            for mut predecessor in predecessors {
+                // Nothing is ever sent, this only returns once the sender is gone.
+                let _ = predecessor.changed().await;
+            }
+            task.await;
+            drop(finished);
+            drop(permit);
+        });
+    }
+
+    /// Waits until every request spawned so far has finished.
+    pub async fn finish(&self) {
+        let _ = self
+            .in_flight
+            .acquire_many(MAX_IN_FLIGHT_REQUESTS as u32)
+            .await;
+    }
+}
@@ -0,0 +1,217 @@
+use serde::Deserialize;
+
+/// Decides which partitions of a topic every member of a consumer group reads.
+/// Assignments are recomputed from scratch on every membership change.
+pub trait Assignor: Send + Sync {
+    /// Splits the partitions `0..partition_count` between the members, given in
+    /// join order with the partitions they currently own. Returns the new
+    /// partitions of every member, in the same order; every partition goes to
+    /// exactly one member and member loads differ by at most one partition.
+    fn assign(&self, partition_count: i32, current: &[Vec<i32>]) -> Vec<Vec<i32>>;
+}
+
+/// Assignment strategy of a consumer group, chosen by the join that creates it.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AssignmentStrategy {
+    #[default]
+    Range,
+    RoundRobin,
+    Sticky,
+}
+
+impl AssignmentStrategy {
+    pub fn assignor(self) -> Box<dyn Assignor> {
+        match self {
+            AssignmentStrategy::Range => Box::new(RangeAssignor),
+            AssignmentStrategy::RoundRobin => Box::new(RoundRobinAssignor),
+            AssignmentStrategy::Sticky => Box::new(StickyAssignor),
+        }
+    }
+}
+
+/// Gives every member a contiguous range of partitions, the first members one
+/// partition more when they do not divide evenly.
+pub struct RangeAssignor;
+
+impl Assignor for RangeAssignor {
+    fn assign(&self, partition_count: i32, current: &[Vec<i32>]) -> Vec<Vec<i32>> {
+        let members = current.len() as i32;
+        let base = partition_count / members;
+        let extra = partition_count % members;
+        let mut start = 0;
+        (0..members)
+            .map(|member| {
+                let len = base + i32::from(member < extra);
+                let range = (start..start + len).collect();
+                start += len;
+                range
+            })
+            .collect()
+    }
+}
+
+/// Deals the partitions out to the members one at a time, in turn.
+pub struct RoundRobinAssignor;
+
+impl Assignor for RoundRobinAssignor {
+    fn assign(&self, partition_count: i32, current: &[Vec<i32>]) -> Vec<Vec<i32>> {
+        let mut assignment = vec![Vec::new(); current.len()];
+        for partition in 0..partition_count {
+            assignment[partition as usize % current.len()].push(partition);
+        }
+        assignment
+    }
+}
+
+/// Balances like the others but lets members keep as many of the partitions they
+/// already own as the balance allows, so that a membership change moves as few
+/// partitions as possible.
+pub struct StickyAssignor;
+
+impl Assignor for StickyAssignor {
+    fn assign(&self, partition_count: i32, current: &[Vec<i32>]) -> Vec<Vec<i32>> {
+        let members = current.len();
+        let base = partition_count as usize / members;
+        let mut extra = partition_count as usize % members;
+        let mut taken = vec![false; partition_count as usize];
+        let mut assignment = Vec::with_capacity(members);
+        for owned in current {
+            let mut owned: Vec<i32> = owned
+                .iter()
+                .copied()
+                .filter(|&partition| {
+                    let free =
+                        (0..partition_count).contains(&partition) && !taken[partition as usize];
+                    if free {
+                        taken[partition as usize] = true;
+                    }
+                    free
+                })
+                .collect();
+            let quota = if owned.len() > base && extra > 0 {
+                extra -= 1;
+                base + 1
+            } else {
+                base
+            };
+            for &partition in owned.iter().skip(quota) {
+                taken[partition as usize] = false;
+            }
+            owned.truncate(quota);
+            assignment.push(owned);
+        }
+        let mut free = (0..partition_count).filter(|&partition| !taken[partition as usize]);
+        for owned in assignment.iter_mut() {
+            while owned.len() < base {
+                owned.push(free.next().unwrap());
+            }
+        }
+        // What is left is one partition for every member still owed one.
+        for (owned, partition) in assignment
+            .iter_mut()
+            .filter(|owned| owned.len() == base)
+            .zip(free)
+        {
+            owned.push(partition);
+        }
+        assignment
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ASSIGNORS: [AssignmentStrategy; 3] = [
+        AssignmentStrategy::Range,
+        AssignmentStrategy::RoundRobin,
+        AssignmentStrategy::Sticky,
+    ];
+
+    /// Checks that every partition went to exactly one member and that member
+    /// loads differ by at most one partition.
+    fn assert_balanced(partition_count: i32, assignment: &[Vec<i32>]) {
+        let mut partitions: Vec<i32> = assignment.iter().flatten().copied().collect();
+        partitions.sort();
+        assert_eq!(partitions, (0..partition_count).collect::<Vec<_>>());
+        let min = assignment.iter().map(Vec::len).min().unwrap();
+        let max = assignment.iter().map(Vec::len).max().unwrap();
+        assert!(max - min <= 1, "unbalanced assignment {assignment:?}");
+    }
+
+    fn moved(before: &[Vec<i32>], after: &[Vec<i32>]) -> usize {
+        before
+            .iter()
+            .zip(after)
+            .map(|(before, after)| before.iter().filter(|p| !after.contains(p)).count())
+            .sum()
+    }
+
+    #[test]
+    fn splits_evenly() {
+        for strategy in ASSIGNORS {
+            let assignment = strategy.assignor().assign(6, &vec![Vec::new(); 3]);
+            assert_balanced(6, &assignment);
+            assert!(assignment.iter().all(|owned| owned.len() == 2));
+        }
+    }
+
+    #[test]
+    fn splits_unevenly() {
+        for strategy in ASSIGNORS {
+            let assignment = strategy.assignor().assign(7, &vec![Vec::new(); 3]);
+            assert_balanced(7, &assignment);
+        }
+        assert_eq!(
+            RangeAssignor.assign(7, &vec![Vec::new(); 3]),
+            vec![vec![0, 1, 2], vec![3, 4], vec![5, 6]]
+        );
+        assert_eq!(
+            RoundRobinAssignor.assign(7, &vec![Vec::new(); 3]),
+            vec![vec![0, 3, 6], vec![1, 4], vec![2, 5]]
+        );
+    }
+
+    #[test]
+    fn leaves_members_beyond_the_partition_count_idle() {
+        for strategy in ASSIGNORS {
+            let assignment = strategy.assignor().assign(2, &vec![Vec::new(); 4]);
+            assert_balanced(2, &assignment);
+            assert_eq!(
+                assignment.iter().filter(|owned| owned.is_empty()).count(),
+                2
+            );
+        }
+    }
+
+    #[test]
+    fn sticky_keeps_partitions_when_a_member_joins() {
+        let before = StickyAssignor.assign(6, &vec![Vec::new(); 2]);
+        let mut current = before.clone();
+        current.push(Vec::new());
+        let after = StickyAssignor.assign(6, &current);
+        assert_balanced(6, &after);
+        // Only the two partitions the new member needs change hands.
+        assert_eq!(moved(&before, &after), 2);
+        assert_eq!(after[2].len(), 2);
+    }
+
+    #[test]
+    fn sticky_keeps_partitions_when_a_member_leaves() {
+        let before = StickyAssignor.assign(6, &vec![Vec::new(); 3]);
+        let current = vec![before[0].clone(), before[2].clone()];
+        let after = StickyAssignor.assign(6, &current);
+        assert_balanced(6, &after);
+        assert!(before[0].iter().all(|p| after[0].contains(p)));
+        assert!(before[2].iter().all(|p| after[1].contains(p)));
+    }
+
+    #[test]
+    fn sticky_ignores_partitions_that_no_longer_exist_or_are_owned_twice() {
+        let after = StickyAssignor.assign(3, &[vec![0, 1, 5], vec![1, 2]]);
+        assert_balanced(3, &after);
+        assert_eq!(after[0], vec![0, 1]);
+        assert_eq!(after[1], vec![2]);
+    }
+}
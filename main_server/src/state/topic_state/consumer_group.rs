@@ -0,0 +1,445 @@
+use std::{collections::HashMap, time::Duration};
+
+use tokio::{sync::mpsc::UnboundedSender, time::Instant};
+
+use crate::state::{protocol::broker_error::BrokerError, topic_state::assignor::Assignor};
+
+/// Group of the consumers that join without naming one, and of the offsets
+/// committed before consumer groups existed.
+pub const DEFAULT_GROUP_ID: &str = "default";
+
+/// Generation of the offset commits that are not fenced by one.
+pub const NO_GENERATION: i32 = -1;
+
+/// Tells a member how a rebalance of its group changed its partitions.
+pub struct RebalanceEvent {
+    pub generation: i32,
+    /// Partitions the member now owns and may start reading.
+    pub assigned: Vec<i32>,
+    /// Partitions the member has to stop reading, commit and give up with
+    /// `REVOKEPARTITIONS`; it owns them until then.
+    pub revoked: Vec<i32>,
+    /// Every partition the member owns after the change.
+    pub partitions: Vec<i32>,
+}
+
+pub struct ConsumerState {
+    pub consumer_id: String,
+    /// Partitions the consumer owns, including those being revoked from it.
+    pub assigned_partitions: Vec<i32>,
+    /// Owned partitions the latest rebalance moved to other members.
+    pub revoking: Vec<i32>,
+    /// Partitions the latest rebalance gave to the consumer that another member
+    /// still has to give up.
+    pub pending: Vec<i32>,
+    /// Since when the consumer has had partitions to give up.
+    pub revoking_since: Option<Instant>,
+    /// Index into `assigned_partitions` of the partition `POLL` last read from.
+    pub last_accessed_partition_index: i32,
+    /// Next offset `POLL` reads from each assigned partition it read before; the
+    /// others start after their committed offset.
+    pub positions: HashMap<i32, i32>,
+    pub last_heartbeat: Instant,
+    /// Whether the consumer is held to the session timeout and gives up revoked
+    /// partitions itself: it joined with version 2 or later, or has sent a
+    /// `HEARTBEAT` since. Older consumers know neither and lose revoked partitions
+    /// right away.
+    pub sends_heartbeats: bool,
+    /// When the consumer last read with `POLL`; `None` until it first does.
+    pub last_poll: Option<Instant>,
+    events: UnboundedSender<RebalanceEvent>,
+}
+
+impl ConsumerState {
+    pub fn new(
+        consumer_id: String,
+        sends_heartbeats: bool,
+        events: UnboundedSender<RebalanceEvent>,
+    ) -> Self {
+        Self {
+            consumer_id,
+            assigned_partitions: Vec::new(),
+            revoking: Vec::new(),
+            pending: Vec::new(),
+            revoking_since: None,
+            last_accessed_partition_index: -1,
+            positions: HashMap::new(),
+            last_heartbeat: Instant::now(),
+            sends_heartbeats,
+            last_poll: None,
+            events,
+        }
+    }
+
+    /// Whether the consumer missed its heartbeats, stopped polling or held on to
+    /// revoked partitions for longer than the group allows.
+    pub fn is_expired(&self, group: &ConsumerGroup, now: Instant) -> bool {
+        (self.sends_heartbeats && now.duration_since(self.last_heartbeat) > group.session_timeout)
+            || self
+                .last_poll
+                .is_some_and(|last_poll| now.duration_since(last_poll) > group.max_poll_interval)
+            || self
+                .revoking_since
+                .is_some_and(|since| now.duration_since(since) > group.session_timeout)
+    }
+
+    fn notify(&self, generation: i32, assigned: Vec<i32>, revoked: Vec<i32>) {
+        // The consumer's connection may already be gone.
+        let _ = self.events.send(RebalanceEvent {
+            generation,
+            assigned,
+            revoked,
+            partitions: self.assigned_partitions.clone(),
+        });
+    }
+}
+
+/// The members of one consumer group of a topic. Its timeouts and assignor are set
+/// by the member whose join created it.
+///
+/// A group can have more members than the topic has partitions; the surplus
+/// members get no partitions and stand by until a rebalance needs them.
+///
+/// Rebalances are cooperative: a partition moving between members is only handed
+/// to its new owner once the previous one has given it up, so no two members ever
+/// read the same partition. Every membership change starts a new generation and
+/// offset commits are only accepted from the current one.
+pub struct ConsumerGroup {
+    pub members: Vec<ConsumerState>,
+    pub generation: i32,
+    /// A member that sends heartbeats and sent none for this long is evicted, as is
+    /// one that takes longer than this to give up revoked partitions.
+    pub session_timeout: Duration,
+    /// A member reading with `POLL` that did not poll for this long is evicted, even
+    /// while it keeps sending heartbeats. Subscribers are kept alive by heartbeats
+    /// alone, their credits already pace them.
+    pub max_poll_interval: Duration,
+    pub assignor: Box<dyn Assignor>,
+}
+
+impl ConsumerGroup {
+    pub fn new(
+        session_timeout: Duration,
+        max_poll_interval: Duration,
+        assignor: Box<dyn Assignor>,
+    ) -> Self {
+        Self {
+            members: Vec::new(),
+            generation: 0,
+            session_timeout,
+            max_poll_interval,
+            assignor,
+        }
+    }
+
+    /// Starts a new generation with the partitions spread over the current members
+    /// by the group's assignor. Members keep the partitions they lose until they
+    /// give them up, except those that cannot, while partitions nobody owns are
+    /// handed out right away. Every member is told about the new generation and
+    /// what changed for it.
+    pub fn rebalance(&mut self, partition_count: i32) {
+        if self.members.is_empty() {
+            return;
+        }
+        self.generation += 1;
+        let current: Vec<Vec<i32>> = self
+            .members
+            .iter()
+            .map(|consumer| consumer.assigned_partitions.clone())
+            .collect();
+        let target = self.assignor.assign(partition_count, &current);
+        let gives_up_partitions: Vec<bool> = self
+            .members
+            .iter()
+            .map(|consumer| consumer.sends_heartbeats)
+            .collect();
+        let now = Instant::now();
+        for (index, (consumer, target)) in self.members.iter_mut().zip(target).enumerate() {
+            let revoked: Vec<i32> = consumer
+                .assigned_partitions
+                .iter()
+                .copied()
+                .filter(|partition| !target.contains(partition))
+                .collect();
+            if consumer.sends_heartbeats {
+                consumer.revoking = revoked.clone();
+            } else {
+                consumer
+                    .assigned_partitions
+                    .retain(|partition| target.contains(partition));
+            }
+            let (assigned, pending): (Vec<i32>, Vec<i32>) = target
+                .into_iter()
+                .filter(|partition| !consumer.assigned_partitions.contains(partition))
+                .partition(|partition| {
+                    !current.iter().enumerate().any(|(owner, owned)| {
+                        owner != index && gives_up_partitions[owner] && owned.contains(partition)
+                    })
+                });
+            consumer.assigned_partitions.extend(&assigned);
+            consumer.pending = pending;
+            consumer.revoking_since = match consumer.revoking_since {
+                _ if consumer.revoking.is_empty() => None,
+                Some(since) => Some(since),
+                None => Some(now),
+            };
+            consumer.notify(self.generation, assigned, revoked);
+        }
+    }
+
+    /// Fails unless `generation` is the group's current generation.
+    pub fn check_generation(&self, group_id: &str, generation: i32) -> Result<(), BrokerError> {
+        if generation != self.generation {
+            return Err(BrokerError::IllegalGeneration {
+                group_id: group_id.to_string(),
+                generation,
+                current_generation: self.generation,
+            });
+        }
+        Ok(())
+    }
+
+    /// Takes the partitions being revoked from the member away from it and hands
+    /// them to the members waiting for them.
+    pub fn revoke_partitions(&mut self, consumer_id: &str) {
+        let consumer = self.member_mut(consumer_id).unwrap();
+        let revoked = std::mem::take(&mut consumer.revoking);
+        consumer
+            .assigned_partitions
+            .retain(|partition| !revoked.contains(partition));
+        consumer.revoking_since = None;
+        let generation = self.generation;
+        for consumer in self.members.iter_mut() {
+            let (assigned, pending) = consumer
+                .pending
+                .iter()
+                .copied()
+                .partition(|partition| revoked.contains(partition));
+            consumer.pending = pending;
+            if !assigned.is_empty() {
+                consumer.assigned_partitions.extend(&assigned);
+                consumer.notify(generation, assigned, Vec::new());
+            }
+        }
+    }
+
+    pub fn member(&self, consumer_id: &str) -> Option<&ConsumerState> {
+        self.members
+            .iter()
+            .find(|consumer| consumer.consumer_id == consumer_id)
+    }
+
+    pub fn member_mut(&mut self, consumer_id: &str) -> Option<&mut ConsumerState> {
+        self.members
+            .iter_mut()
+            .find(|consumer| consumer.consumer_id == consumer_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+    use super::*;
+    use crate::state::topic_state::assignor::AssignmentStrategy;
+
+    const PARTITION_COUNT: i32 = 2;
+
+    fn group() -> ConsumerGroup {
+        ConsumerGroup::new(
+            Duration::from_secs(10),
+            Duration::from_secs(300),
+            AssignmentStrategy::Range.assignor(),
+        )
+    }
+
+    /// Adds a member that sends heartbeats and rebalances, like `JOINCONSUMER` v2.
+    fn join(group: &mut ConsumerGroup, consumer_id: &str) -> UnboundedReceiver<RebalanceEvent> {
+        let (events, received_events) = mpsc::unbounded_channel();
+        group
+            .members
+            .push(ConsumerState::new(consumer_id.to_string(), true, events));
+        group.rebalance(PARTITION_COUNT);
+        received_events
+    }
+
+    /// Removes a member and rebalances, like a leave or an eviction.
+    fn remove(group: &mut ConsumerGroup, consumer_id: &str) {
+        group
+            .members
+            .retain(|consumer| consumer.consumer_id != consumer_id);
+        group.rebalance(PARTITION_COUNT);
+    }
+
+    fn owned(group: &ConsumerGroup, consumer_id: &str) -> Vec<i32> {
+        let mut partitions = group
+            .member(consumer_id)
+            .unwrap()
+            .assigned_partitions
+            .clone();
+        partitions.sort();
+        partitions
+    }
+
+    /// Checks that no partition is owned by two members and that every pending
+    /// partition is still being revoked from its owner.
+    fn assert_exclusive(group: &ConsumerGroup) {
+        let mut partitions: Vec<i32> = group
+            .members
+            .iter()
+            .flat_map(|consumer| consumer.assigned_partitions.iter().copied())
+            .collect();
+        let count = partitions.len();
+        partitions.sort();
+        partitions.dedup();
+        assert_eq!(partitions.len(), count, "partition owned twice");
+        for consumer in group.members.iter() {
+            for partition in consumer.pending.iter() {
+                assert!(
+                    group
+                        .members
+                        .iter()
+                        .any(|owner| owner.revoking.contains(partition)),
+                    "partition {partition} pending without being revoked"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn revoke_hands_partition_over() {
+        let mut group = group();
+        let _a_events = join(&mut group, "a");
+        assert_eq!(owned(&group, "a"), vec![0, 1]);
+
+        let mut b_events = join(&mut group, "b");
+        assert_eq!(group.generation, 2);
+        assert_eq!(owned(&group, "a"), vec![0, 1]);
+        assert_eq!(group.member("a").unwrap().revoking, vec![1]);
+        assert!(group.member("a").unwrap().revoking_since.is_some());
+        assert_eq!(owned(&group, "b"), Vec::<i32>::new());
+        assert_eq!(group.member("b").unwrap().pending, vec![1]);
+        assert_exclusive(&group);
+        assert!(b_events.try_recv().unwrap().assigned.is_empty());
+
+        group.revoke_partitions("a");
+        assert_eq!(owned(&group, "a"), vec![0]);
+        assert!(group.member("a").unwrap().revoking.is_empty());
+        assert!(group.member("a").unwrap().revoking_since.is_none());
+        assert_eq!(owned(&group, "b"), vec![1]);
+        assert!(group.member("b").unwrap().pending.is_empty());
+        let event = b_events.try_recv().unwrap();
+        assert_eq!(event.generation, 2);
+        assert_eq!(event.assigned, vec![1]);
+        assert_exclusive(&group);
+    }
+
+    #[test]
+    fn revoking_member_leaves() {
+        let mut group = group();
+        let _a_events = join(&mut group, "a");
+        let mut b_events = join(&mut group, "b");
+        assert_eq!(group.member("b").unwrap().pending, vec![1]);
+        while b_events.try_recv().is_ok() {}
+
+        remove(&mut group, "a");
+        assert_eq!(owned(&group, "b"), vec![0, 1]);
+        assert!(group.member("b").unwrap().pending.is_empty());
+        let event = b_events.try_recv().unwrap();
+        assert_eq!(event.generation, 3);
+        assert_eq!(event.partitions.len(), 2);
+    }
+
+    #[test]
+    fn revoking_member_expires() {
+        let mut group = group();
+        let _a_events = join(&mut group, "a");
+        let _b_events = join(&mut group, "b");
+        let deadline = group.member("a").unwrap().revoking_since.unwrap() + group.session_timeout;
+        // Heartbeats do not keep a member alive past the time allowed to revoke.
+        group.member_mut("a").unwrap().last_heartbeat = deadline;
+        group.member_mut("b").unwrap().last_heartbeat = deadline;
+        let a = group.member("a").unwrap();
+        let b = group.member("b").unwrap();
+        assert!(!a.is_expired(&group, deadline));
+        let after_deadline = deadline + Duration::from_millis(1);
+        assert!(a.is_expired(&group, after_deadline));
+        assert!(!b.is_expired(&group, after_deadline));
+
+        remove(&mut group, "a");
+        assert_eq!(owned(&group, "b"), vec![0, 1]);
+        assert!(group.member("b").unwrap().pending.is_empty());
+    }
+
+    #[test]
+    fn rebalance_before_revoke() {
+        let mut group = group();
+        let _a_events = join(&mut group, "a");
+        let _b_events = join(&mut group, "b");
+        assert_eq!(group.member("a").unwrap().revoking, vec![1]);
+        let revoking_since = group.member("a").unwrap().revoking_since;
+
+        let _c_events = join(&mut group, "c");
+        assert_eq!(group.generation, 3);
+        assert_exclusive(&group);
+        // Still revoking, so the deadline to give partitions up is not reset.
+        assert_eq!(group.member("a").unwrap().revoking_since, revoking_since);
+
+        for consumer_id in ["a", "b", "c"] {
+            if !group.member(consumer_id).unwrap().revoking.is_empty() {
+                group.revoke_partitions(consumer_id);
+                assert_exclusive(&group);
+            }
+        }
+        let mut partitions: Vec<i32> = ["a", "b", "c"]
+            .into_iter()
+            .flat_map(|consumer_id| owned(&group, consumer_id))
+            .collect();
+        partitions.sort();
+        assert_eq!(partitions, vec![0, 1]);
+        assert!(
+            group
+                .members
+                .iter()
+                .all(|consumer| consumer.pending.is_empty())
+        );
+    }
+
+    #[test]
+    fn stale_generation_is_rejected() {
+        let mut group = group();
+        let _a_events = join(&mut group, "a");
+        let _b_events = join(&mut group, "b");
+        let stale_generation = group.generation;
+        let _c_events = join(&mut group, "c");
+
+        assert!(matches!(
+            group.check_generation("g", stale_generation),
+            Err(BrokerError::IllegalGeneration {
+                generation,
+                current_generation,
+                ..
+            }) if generation == stale_generation && current_generation == stale_generation + 1
+        ));
+        assert!(group.check_generation("g", group.generation).is_ok());
+    }
+
+    #[test]
+    fn members_without_heartbeats_give_partitions_up_at_once() {
+        let mut group = group();
+        let (events, _a_events) = mpsc::unbounded_channel();
+        group
+            .members
+            .push(ConsumerState::new("a".to_string(), false, events));
+        group.rebalance(PARTITION_COUNT);
+        let _b_events = join(&mut group, "b");
+
+        assert_eq!(owned(&group, "a"), vec![0]);
+        assert!(group.member("a").unwrap().revoking.is_empty());
+        assert_eq!(owned(&group, "b"), vec![1]);
+        assert!(group.member("b").unwrap().pending.is_empty());
+        let a = group.member("a").unwrap();
+        let later = a.last_heartbeat + group.session_timeout + Duration::from_secs(1);
+        assert!(!a.is_expired(&group, later));
+    }
+}
@@ -1 +1,5 @@
+pub mod assignor;
+pub mod consumer_group;
+pub mod session_timeout_task;
+#[allow(clippy::module_inception)]
 pub mod topic_state;
@@ -0,0 +1,16 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::{sync::RwLock, time::Instant};
+
+use crate::state::topic_state::topic_state::Topic;
+
+/// Background loop evicting the consumers that outlived their group's session
+/// timeout or max poll interval, checked every `interval`.
+pub async fn run_session_timeouts(topics_data: Arc<RwLock<Topic>>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let mut topics_guard = topics_data.write().await;
+        topics_guard.evict_expired_consumers(Instant::now());
+    }
+}
@@ -2,110 +2,414 @@ use std::{
     collections::{HashMap, HashSet},
     env,
     hash::{DefaultHasher, Hash, Hasher},
+    io::ErrorKind,
+    sync::Arc,
+    time::Duration,
 };
 
 use tokio::{
-    fs::{self, File},
-    io::AsyncWriteExt,
+    fs,
+    sync::{Notify, mpsc::UnboundedSender},
+    time::Instant,
 };
 
-use crate::state::message_state::store::MessageStore;
-
-pub struct ConsumerState {
-    pub consumer_id: String,
-    pub assigned_partitions: Vec<i32>,
-    pub last_accessed_partition_index: i32,
-}
+use crate::state::{
+    config::{broker_config::BrokerConfig, topic_config::TopicConfig},
+    message_from_client::{
+        message_for_consumer::message::{Fetch, JoinConsumer, Poll, RevokePartitions},
+        message_for_producer::message::ProduceRecord,
+    },
+    message_state::{
+        compression::Codec, record::Record, segment::PendingSync, store::MessageStore,
+    },
+    protocol::broker_error::BrokerError,
+    topic_state::consumer_group::{ConsumerGroup, ConsumerState, NO_GENERATION, RebalanceEvent},
+};
 
 pub struct Topic {
     pub topics_set: HashSet<String>,
     pub topics_data: HashMap<String, Message>,
     pub messages_store: MessageStore,
-    pub consumers: HashMap<String, Vec<ConsumerState>>,
+    /// Members of every consumer group, keyed by topic and then by group id.
+    pub consumers: HashMap<String, HashMap<String, ConsumerGroup>>,
+    /// Timeouts of the groups whose first member did not choose its own.
+    pub default_session_timeout: Duration,
+    pub default_max_poll_interval: Duration,
 }
 
 pub struct Message {
     pub partition_count: i32,
     pub prev_written_partition: i32,
+    /// Per partition, wakes the fetches waiting for records to be appended to it.
+    pub new_data: Vec<Arc<Notify>>,
+    /// Wakes the subscriptions waiting for records on any partition of the topic,
+    /// or for a rebalance to give them partitions.
+    pub topic_new_data: Arc<Notify>,
+}
+
+/// Records read by a fetch, with the offset the next record appended to the
+/// partition will get.
+pub struct FetchedRecords {
+    pub records: Vec<Record>,
+    pub high_watermark: i32,
+}
+
+impl FetchedRecords {
+    /// Whether a fetch asking for `min_bytes` bytes can be answered with these
+    /// records rather than wait for more.
+    pub fn is_enough(&self, min_bytes: i32) -> bool {
+        let bytes: usize = self.records.iter().map(Record::encoded_len).sum();
+        !self.records.is_empty() && bytes >= min_bytes.max(0) as usize
+    }
+}
+
+/// The partitions a consumer owns in the current generation of its group.
+pub struct Assignment {
+    pub group_id: String,
+    pub generation: i32,
+    /// Owned partitions with the group's committed offset in each.
+    pub partitions: Vec<(i32, Option<i32>)>,
+    /// Owned partitions the consumer has to give up.
+    pub revoking: Vec<i32>,
+    /// Partitions the consumer gets once their previous owner gives them up.
+    pub pending: Vec<i32>,
+}
+
+/// Where a produced record ended up. For a batch, `offset` is the offset of its
+/// first record.
+pub struct ProducedRecord {
+    pub partition: i32,
+    pub offset: i32,
+    pub timestamp: i64,
 }
 
 impl Topic {
-    pub fn new() -> Self {
+    pub fn new(broker_config: &BrokerConfig) -> Self {
         Self {
             topics_set: HashSet::new(),
             topics_data: HashMap::new(),
-            messages_store: MessageStore::new(),
+            messages_store: MessageStore::new(broker_config.fsync_policy),
             consumers: HashMap::new(),
+            default_session_timeout: broker_config.consumer_session_timeout,
+            default_max_poll_interval: broker_config.consumer_max_poll_interval,
+        }
+    }
+
+    /// Re-registers every topic found under `logs/<topic>/<partition>/` so the broker
+    /// picks up where it left off after a restart.
+    pub async fn recover_from_disk(&mut self) -> std::io::Result<()> {
+        let logs_path = env::current_dir()?.join("logs");
+        fs::create_dir_all(&logs_path).await?;
+        let mut topic_entries = fs::read_dir(&logs_path).await?;
+        while let Some(topic_entry) = topic_entries.next_entry().await? {
+            if !topic_entry.file_type().await?.is_dir() {
+                continue;
+            }
+            let Ok(topic_name) = topic_entry.file_name().into_string() else {
+                continue;
+            };
+            let mut partition_count = 0;
+            let mut partition_entries = fs::read_dir(topic_entry.path()).await?;
+            while let Some(partition_entry) = partition_entries.next_entry().await? {
+                if !partition_entry.file_type().await?.is_dir() {
+                    continue;
+                }
+                let partition = partition_entry
+                    .file_name()
+                    .to_str()
+                    .and_then(|name| name.parse::<i32>().ok());
+                if let Some(partition) = partition {
+                    partition_count = partition_count.max(partition + 1);
+                }
+            }
+            if partition_count == 0 || self.topics_set.contains(&topic_name) {
+                continue;
+            }
+            let config = match fs::read(topic_entry.path().join("config.json")).await {
+                Ok(bytes) => serde_json::from_slice::<TopicConfig>(&bytes)
+                    .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?,
+                Err(e) if e.kind() == ErrorKind::NotFound => TopicConfig::without_config_file(),
+                Err(e) => return Err(e),
+            };
+            self.messages_store
+                .recover_topic(topic_name.clone(), partition_count, config)
+                .await?;
+            self.register_topic(topic_name, partition_count);
         }
+        Ok(())
     }
 
-    pub async fn add_topic(&mut self, topic_name: String, partitions: i32) {
+    pub async fn add_topic(
+        &mut self,
+        topic_name: String,
+        partitions: i32,
+        config: TopicConfig,
+    ) -> Result<(), BrokerError> {
         if self.topics_set.contains(&topic_name) {
-            return;
+            return Err(BrokerError::TopicAlreadyExists { topic: topic_name });
         }
-        let path = env::current_dir().unwrap().join("logs").join(&topic_name);
-        fs::create_dir(&path).await.unwrap();
+        if partitions < 1 {
+            return Err(BrokerError::invalid_request(
+                "a topic needs at least one partition",
+            ));
+        }
+        let path = env::current_dir()?.join("logs").join(&topic_name);
+        fs::create_dir_all(&path).await?;
         for i in 0..partitions {
             let partition_path = path.join(format!("{}", i));
-            fs::create_dir(&partition_path).await.unwrap();
+            fs::create_dir_all(&partition_path).await?;
         }
+        fs::write(
+            path.join("config.json"),
+            serde_json::to_vec(&config).unwrap(),
+        )
+        .await?;
+        self.messages_store
+            .add_topic(topic_name.clone(), partitions, config);
+        self.register_topic(topic_name, partitions);
+        Ok(())
+    }
+
+    fn register_topic(&mut self, topic_name: String, partitions: i32) {
         self.topics_set.insert(topic_name.clone());
         self.topics_data.insert(
             topic_name.clone(),
             Message {
                 partition_count: partitions,
                 prev_written_partition: -1,
+                new_data: (0..partitions).map(|_| Arc::new(Notify::new())).collect(),
+                topic_new_data: Arc::new(Notify::new()),
             },
         );
-        self.messages_store
-            .add_topic(topic_name.clone(), partitions);
-        self.consumers.insert(topic_name, Vec::new());
+        self.consumers.insert(topic_name, HashMap::new());
     }
 
-    pub async fn delete_topic(&mut self, topic_name: &str) {
+    pub async fn delete_topic(&mut self, topic_name: &str) -> Result<(), BrokerError> {
         if !self.topics_set.contains(topic_name) {
-            return;
+            return Err(BrokerError::UnknownTopic {
+                topic: topic_name.to_string(),
+            });
+        }
+        let path = env::current_dir()?.join("logs").join(topic_name);
+        fs::remove_dir_all(path).await?;
+        // A topic created later under the same name starts without offsets.
+        let offsets_path = env::current_dir()?.join("offsets").join(topic_name);
+        match fs::remove_dir_all(offsets_path).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
         }
-        let path = env::current_dir().unwrap().join("logs").join(&topic_name);
-        fs::remove_dir_all(path).await.unwrap();
         self.topics_set.remove(topic_name);
-        self.topics_data.remove(topic_name);
+        if let Some(topic_data) = self.topics_data.remove(topic_name) {
+            // Waiting fetches find out the topic is gone.
+            for new_data in topic_data.new_data {
+                new_data.notify_waiters();
+            }
+            topic_data.topic_new_data.notify_waiters();
+        }
         self.messages_store.delete_topic(topic_name);
         self.consumers.remove(topic_name);
+        Ok(())
+    }
+
+    /// Checks that the topic exists and has `partition`.
+    fn check_partition(&self, topic_name: &str, partition: i32) -> Result<(), BrokerError> {
+        let Some(topic_data) = self.topics_data.get(topic_name) else {
+            return Err(BrokerError::UnknownTopic {
+                topic: topic_name.to_string(),
+            });
+        };
+        if partition < 0 || partition >= topic_data.partition_count {
+            return Err(BrokerError::UnknownPartition {
+                topic: topic_name.to_string(),
+                partition,
+            });
+        }
+        Ok(())
     }
 
-    pub async fn send_message(&mut self, key: Option<String>, data: Vec<u8>, topic_name: String) {
+    /// Appends a message to the partition its key picks. The returned sync is to
+    /// be waited for, after unlocking the store, before acknowledging it.
+    pub async fn send_message(
+        &mut self,
+        key: Option<String>,
+        data: Option<Vec<u8>>,
+        topic_name: String,
+        timestamp: Option<i64>,
+    ) -> Result<(ProducedRecord, PendingSync), BrokerError> {
         if !self.topics_set.contains(&topic_name) {
-            return;
+            return Err(BrokerError::UnknownTopic { topic: topic_name });
+        }
+        let index = self.choose_partition(key.as_deref(), &topic_name);
+        let key_bytes = key.map(String::into_bytes);
+        match self
+            .messages_store
+            .append_message(&topic_name, index, key_bytes, data, timestamp)
+            .await
+        {
+            Ok(((offset, timestamp), sync)) => {
+                self.notify_new_data(&topic_name, index);
+                let produced = ProducedRecord {
+                    partition: index,
+                    offset,
+                    timestamp,
+                };
+                Ok((produced, sync))
+            }
+            Err(e) => {
+                eprintln!(
+                    "Failed to append to {}/{}; err = {:?}",
+                    topic_name, index, e
+                );
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Appends a batch of `record_count` records compressed by the producer. `key`
+    /// only picks the partition, the whole batch goes to the same one. Synced like
+    /// `send_message`.
+    pub async fn send_batch(
+        &mut self,
+        key: Option<String>,
+        topic_name: String,
+        codec: Codec,
+        record_count: i32,
+        records: Vec<u8>,
+        timestamp: Option<i64>,
+    ) -> Result<(ProducedRecord, PendingSync), BrokerError> {
+        if !self.topics_set.contains(&topic_name) {
+            return Err(BrokerError::UnknownTopic { topic: topic_name });
+        }
+        let index = self.choose_partition(key.as_deref(), &topic_name);
+        match self
+            .messages_store
+            .append_batch(&topic_name, index, codec, record_count, &records, timestamp)
+            .await
+        {
+            Ok(((offset, timestamp), sync)) => {
+                self.notify_new_data(&topic_name, index);
+                let produced = ProducedRecord {
+                    partition: index,
+                    offset,
+                    timestamp,
+                };
+                Ok((produced, sync))
+            }
+            Err(e) => {
+                eprintln!(
+                    "Failed to append batch to {}/{}; err = {:?}",
+                    topic_name, index, e
+                );
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Appends `records` to one partition chosen by the producer, all of them or
+    /// none. Synced like `send_message`.
+    pub async fn send_records(
+        &mut self,
+        topic_name: &str,
+        partition: i32,
+        records: Vec<ProduceRecord>,
+    ) -> Result<(Vec<ProducedRecord>, PendingSync), BrokerError> {
+        self.check_partition(topic_name, partition)?;
+        if records.is_empty() {
+            return Err(BrokerError::invalid_request(format!(
+                "no records for partition {} of topic {}",
+                partition, topic_name
+            )));
+        }
+        let messages = records
+            .into_iter()
+            .map(|record| {
+                (
+                    record.key.map(String::into_bytes),
+                    record.data,
+                    record.timestamp,
+                )
+            })
+            .collect();
+        match self
+            .messages_store
+            .append_messages(topic_name, partition, messages)
+            .await
+        {
+            Ok((appended, sync)) => {
+                self.notify_new_data(topic_name, partition);
+                let produced = appended
+                    .into_iter()
+                    .map(|(offset, timestamp)| ProducedRecord {
+                        partition,
+                        offset,
+                        timestamp,
+                    })
+                    .collect();
+                Ok((produced, sync))
+            }
+            Err(e) => {
+                eprintln!(
+                    "Failed to append to {}/{}; err = {:?}",
+                    topic_name, partition, e
+                );
+                Err(e.into())
+            }
+        }
+    }
+
+    fn notify_new_data(&self, topic_name: &str, partition: i32) {
+        let topic_data = self.topics_data.get(topic_name).unwrap();
+        topic_data.new_data[partition as usize].notify_waiters();
+        topic_data.topic_new_data.notify_waiters();
+    }
+
+    /// Wakes the subscriptions of the topic to pick up partitions handed to them.
+    fn notify_reassigned(&self, topic_name: &str) {
+        self.topics_data
+            .get(topic_name)
+            .unwrap()
+            .topic_new_data
+            .notify_waiters();
+    }
+
+    /// Signalled whenever records are appended to the partition or the topic is
+    /// deleted.
+    pub fn new_data_notify(
+        &self,
+        topic_name: &str,
+        partition: i32,
+    ) -> Result<Arc<Notify>, BrokerError> {
+        self.check_partition(topic_name, partition)?;
+        Ok(Arc::clone(
+            &self.topics_data.get(topic_name).unwrap().new_data[partition as usize],
+        ))
+    }
+
+    /// Signalled whenever records are appended to any partition of the topic, its
+    /// partitions are handed to other consumers or the topic is deleted.
+    pub fn topic_new_data_notify(&self, topic_name: &str) -> Result<Arc<Notify>, BrokerError> {
+        match self.topics_data.get(topic_name) {
+            Some(topic_data) => Ok(Arc::clone(&topic_data.topic_new_data)),
+            None => Err(BrokerError::UnknownTopic {
+                topic: topic_name.to_string(),
+            }),
         }
-        let required_topic = self.topics_data.get(&topic_name).unwrap();
+    }
+
+    /// Hashes the key when there is one and goes round robin otherwise.
+    fn choose_partition(&mut self, key: Option<&str>, topic_name: &str) -> i32 {
+        let required_topic = self.topics_data.get(topic_name).unwrap();
         let index;
-        if key.is_some() {
-            index = self.string_to_index(&key.unwrap(), required_topic.partition_count) as i32;
+        if let Some(key) = key {
+            index = self.string_to_index(key, required_topic.partition_count) as i32;
         } else {
             index = (required_topic.prev_written_partition + 1) % required_topic.partition_count;
             self.topics_data
-                .get_mut(&topic_name)
+                .get_mut(topic_name)
                 .unwrap()
                 .prev_written_partition = index;
         }
-        let (cache_size, total_messages_count) =
-            self.messages_store.write_to_cache(&topic_name, index, data);
-        if cache_size == 10 {
-            let file_number_to_write_to = (total_messages_count / 10) * 10;
-            let vec_data = self.messages_store.clear_vec_and_return(&topic_name, index);
-            let file_path = env::current_dir()
-                .unwrap()
-                .join("logs")
-                .join(topic_name)
-                .join(format!("{}", index))
-                .join(format!("{}.log", file_number_to_write_to));
-            let mut file = File::create(file_path).await.unwrap();
-            for line in vec_data {
-                let _ = file.write_all(&line).await.unwrap();
-                let _ = file.write_all(b"\n").await.unwrap();
-            }
-        }
+        index
     }
 
     fn string_to_index(&self, s: &str, n: i32) -> u64 {
@@ -115,125 +419,477 @@ impl Topic {
         hash % (n as u64)
     }
 
-    pub fn add_consumer(&mut self, connection_id: &str, topic_name: &str) -> i32 {
+    /// Adds the consumer to its group and rebalances it; `events` receives every
+    /// change to the consumer's partitions from then on. Consumers beyond the
+    /// partition count join as standbys.
+    pub fn add_consumer(
+        &mut self,
+        connection_id: &str,
+        request: &JoinConsumer,
+        sends_heartbeats: bool,
+        events: UnboundedSender<RebalanceEvent>,
+    ) -> Result<(), BrokerError> {
+        let JoinConsumer {
+            topic_name,
+            group_id,
+            session_timeout_ms,
+            max_poll_interval_ms,
+            assignment_strategy,
+        } = request;
         if !self.topics_set.contains(topic_name) {
-            return -1;
+            return Err(BrokerError::UnknownTopic {
+                topic: topic_name.to_string(),
+            });
         }
-        let partition_count = self.topics_data.get(topic_name).unwrap().partition_count;
-        // no consumers
-        if self.consumers.get(topic_name).unwrap().is_empty() {
-            let mut consumer_state_vec = Vec::new();
-            for i in 0..partition_count {
-                consumer_state_vec.push(i);
-            }
-            let consumer_state = ConsumerState {
-                consumer_id: connection_id.to_string(),
-                assigned_partitions: consumer_state_vec,
-                last_accessed_partition_index: -1,
-            };
-            self.consumers
-                .get_mut(topic_name)
-                .unwrap()
-                .push(consumer_state);
-            return 0;
+        // The group id names the directory its offsets are committed to.
+        if group_id.is_empty()
+            || group_id == "."
+            || group_id == ".."
+            || group_id.contains(['/', '\\'])
+        {
+            return Err(BrokerError::invalid_request(
+                "group_id must be a non-empty name without path separators",
+            ));
         }
-        let consumers_count = self.consumers.get(topic_name).unwrap().len() as i32;
-        if consumers_count >= partition_count {
-            return -1;
+        if *session_timeout_ms < 0 || *max_poll_interval_ms < 0 {
+            return Err(BrokerError::invalid_request(
+                "session_timeout_ms and max_poll_interval_ms must not be negative",
+            ));
         }
-        let first_consumer_data = self
+        if self.consumer_group(connection_id, topic_name).is_ok() {
+            return Err(BrokerError::invalid_request(format!(
+                "already a consumer of topic {}",
+                topic_name
+            )));
+        }
+        let partition_count = self.topics_data.get(topic_name).unwrap().partition_count;
+        let session_timeout = match *session_timeout_ms {
+            0 => self.default_session_timeout,
+            millis => Duration::from_millis(millis as u64),
+        };
+        let max_poll_interval = match *max_poll_interval_ms {
+            0 => self.default_max_poll_interval,
+            millis => Duration::from_millis(millis as u64),
+        };
+        let group = self
             .consumers
             .get_mut(topic_name)
             .unwrap()
-            .get_mut(0)
-            .unwrap()
-            .assigned_partitions
-            .pop()
-            .unwrap();
+            .entry(group_id.to_string())
+            .or_insert_with(|| {
+                ConsumerGroup::new(
+                    session_timeout,
+                    max_poll_interval,
+                    assignment_strategy.assignor(),
+                )
+            });
+        group.members.push(ConsumerState::new(
+            connection_id.to_string(),
+            sends_heartbeats,
+            events,
+        ));
+        group.rebalance(partition_count);
+        self.notify_reassigned(topic_name);
+        Ok(())
+    }
+
+    pub fn leave_consumer(
+        &mut self,
+        connection_id: &str,
+        topic_name: &str,
+    ) -> Result<(), BrokerError> {
+        let group_id = self.consumer_group(connection_id, topic_name)?;
+        self.remove_consumer(connection_id, topic_name, &group_id);
+        Ok(())
+    }
 
-        let partition_for_new_consumer = vec![first_consumer_data];
-        let consumer_state = ConsumerState {
-            consumer_id: connection_id.to_string(),
-            assigned_partitions: partition_for_new_consumer,
-            last_accessed_partition_index: -1,
+    /// The group the consumer joined the topic with.
+    pub fn consumer_group(
+        &self,
+        connection_id: &str,
+        topic_name: &str,
+    ) -> Result<String, BrokerError> {
+        let Some(groups) = self.consumers.get(topic_name) else {
+            return Err(BrokerError::UnknownTopic {
+                topic: topic_name.to_string(),
+            });
         };
-        self.consumers
+        groups
+            .iter()
+            .find(|(_, group)| group.member(connection_id).is_some())
+            .map(|(group_id, _)| group_id.clone())
+            .ok_or_else(|| BrokerError::UnknownConsumer {
+                topic: topic_name.to_string(),
+            })
+    }
+
+    fn consumer_mut(
+        &mut self,
+        connection_id: &str,
+        topic_name: &str,
+    ) -> Result<&mut ConsumerState, BrokerError> {
+        let group_id = self.consumer_group(connection_id, topic_name)?;
+        Ok(self
+            .consumers
             .get_mut(topic_name)
             .unwrap()
-            .push(consumer_state);
-        return 0;
+            .get_mut(&group_id)
+            .unwrap()
+            .member_mut(connection_id)
+            .unwrap())
     }
 
-    pub fn leave_consumer(&mut self, connection_id: &str, topic_name: &str) {
-        if !self.topics_set.contains(topic_name) {
-            return;
+    pub fn revoke_partitions(
+        &mut self,
+        connection_id: &str,
+        request: &RevokePartitions,
+    ) -> Result<(), BrokerError> {
+        let RevokePartitions {
+            topic_name,
+            generation,
+        } = request;
+        let group_id = self.consumer_group(connection_id, topic_name)?;
+        let group = self
+            .consumers
+            .get_mut(topic_name)
+            .unwrap()
+            .get_mut(&group_id)
+            .unwrap();
+        group.check_generation(&group_id, *generation)?;
+        group.revoke_partitions(connection_id);
+        self.notify_reassigned(topic_name);
+        Ok(())
+    }
+
+    pub fn describe_assignment(
+        &self,
+        connection_id: &str,
+        topic_name: &str,
+    ) -> Result<Assignment, BrokerError> {
+        let group_id = self.consumer_group(connection_id, topic_name)?;
+        let group = &self.consumers.get(topic_name).unwrap()[&group_id];
+        let consumer = group.member(connection_id).unwrap();
+        Ok(Assignment {
+            partitions: self.committed_offsets(
+                topic_name,
+                &group_id,
+                &consumer.assigned_partitions,
+            ),
+            revoking: consumer.revoking.clone(),
+            pending: consumer.pending.clone(),
+            generation: group.generation,
+            group_id,
+        })
+    }
+
+    /// The group's committed offset in each of `partitions`.
+    pub fn committed_offsets(
+        &self,
+        topic_name: &str,
+        group_id: &str,
+        partitions: &[i32],
+    ) -> Vec<(i32, Option<i32>)> {
+        partitions
+            .iter()
+            .map(|partition| {
+                (
+                    *partition,
+                    self.messages_store
+                        .get_committed_offset(partition, topic_name, group_id),
+                )
+            })
+            .collect()
+    }
+
+    /// Keeps the consumer's session alive for another session timeout.
+    pub fn heartbeat(&mut self, connection_id: &str, topic_name: &str) -> Result<(), BrokerError> {
+        let consumer = self.consumer_mut(connection_id, topic_name)?;
+        consumer.last_heartbeat = Instant::now();
+        consumer.sends_heartbeats = true;
+        Ok(())
+    }
+
+    /// Evicts every consumer whose session timed out or who stopped polling,
+    /// rebalancing its partitions over the rest of its group.
+    pub fn evict_expired_consumers(&mut self, now: Instant) {
+        let mut expired = Vec::new();
+        for (topic_name, groups) in self.consumers.iter() {
+            for (group_id, group) in groups.iter() {
+                for consumer in group.members.iter() {
+                    if consumer.is_expired(group, now) {
+                        expired.push((
+                            consumer.consumer_id.clone(),
+                            topic_name.clone(),
+                            group_id.clone(),
+                        ));
+                    }
+                }
+            }
+        }
+        for (connection_id, topic_name, group_id) in expired {
+            println!(
+                "Evicting consumer {} from group {} of topic {}",
+                connection_id, group_id, topic_name
+            );
+            self.remove_consumer(&connection_id, &topic_name, &group_id);
         }
-        let consumers_count = self.consumers.get(topic_name).unwrap().len() as i32;
-        let first_vec = self.consumers.get(topic_name).unwrap().get(0).unwrap();
-        if consumers_count <= 1 && first_vec.consumer_id == connection_id {
-            self.consumers.get_mut(topic_name).unwrap().clear();
+    }
+
+    /// Removes the consumer from its group and rebalances the partitions over the
+    /// remaining members. The group goes away with its last member.
+    fn remove_consumer(&mut self, connection_id: &str, topic_name: &str, group_id: &str) {
+        let partition_count = self.topics_data.get(topic_name).unwrap().partition_count;
+        let groups = self.consumers.get_mut(topic_name).unwrap();
+        let group = groups.get_mut(group_id).unwrap();
+        group
+            .members
+            .retain(|consumer| consumer.consumer_id != connection_id);
+        if group.members.is_empty() {
+            groups.remove(group_id);
             return;
         }
-        let mut index = 0;
-        for vec_data in self.consumers.get(topic_name).unwrap() {
-            if vec_data.consumer_id == connection_id {
-                break;
-            } else {
-                index += 1;
-            }
+        group.rebalance(partition_count);
+        self.notify_reassigned(topic_name);
+    }
+
+    /// Reads the next records for `POLL`, going round robin over the partitions
+    /// assigned to the consumer from the one after the partition it last read from.
+    /// A partition is read from where the previous poll left off, or from after its
+    /// committed offset, or from its start when nothing was committed. A partition
+    /// that cannot be read is skipped.
+    pub async fn poll(
+        &mut self,
+        connection_id: &str,
+        request: &Poll,
+    ) -> Result<Vec<(i32, Vec<Record>)>, BrokerError> {
+        let Poll {
+            topic_name,
+            max_records,
+            max_bytes,
+        } = request;
+        if *max_records < 1 || *max_bytes < 1 {
+            return Err(BrokerError::invalid_request(
+                "max_records and max_bytes must be positive",
+            ));
         }
-        let mut removed_vec = self.consumers.get_mut(topic_name).unwrap().remove(index);
-        self.consumers
+        let group_id = self.consumer_group(connection_id, topic_name)?;
+        let consumer = self
+            .consumers
             .get_mut(topic_name)
             .unwrap()
-            .get_mut(0)
+            .get_mut(&group_id)
             .unwrap()
-            .assigned_partitions
-            .extend(removed_vec.assigned_partitions.drain(..));
+            .member_mut(connection_id)
+            .unwrap();
+        consumer.last_poll = Some(Instant::now());
+        let partitions = &consumer.assigned_partitions;
+        consumer
+            .positions
+            .retain(|partition, _| partitions.contains(partition));
+        let mut polled = Vec::new();
+        let mut remaining_records = *max_records;
+        let mut remaining_bytes = *max_bytes as usize;
+        let first = (consumer.last_accessed_partition_index + 1).max(0) as usize;
+        for i in 0..partitions.len() {
+            let index = (first + i) % partitions.len();
+            let partition = partitions[index];
+            let offset = self.messages_store.read_position(
+                &partition,
+                topic_name,
+                &group_id,
+                consumer.positions.get(&partition).copied(),
+            );
+            let mut records = match self
+                .messages_store
+                .fetch_records(
+                    &partition,
+                    topic_name,
+                    offset,
+                    remaining_records,
+                    remaining_bytes as i32,
+                    false,
+                )
+                .await
+            {
+                Ok(records) => records,
+                Err(e) => {
+                    // The other partitions can still be read.
+                    eprintln!(
+                        "Failed to poll {}/{} at offset {}; err = {:?}",
+                        topic_name, partition, offset, e
+                    );
+                    continue;
+                }
+            };
+            if !polled.is_empty() {
+                // Only the first record of the whole poll may exceed `max_bytes`.
+                let mut bytes = 0;
+                records.retain(|record| {
+                    bytes += record.encoded_len();
+                    bytes <= remaining_bytes
+                });
+            }
+            let Some(last_record) = records.last() else {
+                continue;
+            };
+            consumer
+                .positions
+                .insert(partition, last_record.last_offset() as i32 + 1);
+            consumer.last_accessed_partition_index = index as i32;
+            remaining_records -= records.len() as i32;
+            let bytes: usize = records.iter().map(Record::encoded_len).sum();
+            remaining_bytes = remaining_bytes.saturating_sub(bytes);
+            polled.push((partition, records));
+            if remaining_records == 0 || remaining_bytes == 0 {
+                break;
+            }
+        }
+        Ok(polled)
+    }
+
+    /// The partitions of the topic currently assigned to the consumer.
+    pub fn assigned_partitions(
+        &self,
+        connection_id: &str,
+        topic_name: &str,
+    ) -> Result<Vec<i32>, BrokerError> {
+        let group_id = self.consumer_group(connection_id, topic_name)?;
+        let consumer = self.consumers.get(topic_name).unwrap()[&group_id]
+            .member(connection_id)
+            .unwrap();
+        Ok(consumer.assigned_partitions.clone())
     }
 
     pub fn disconnect_user(&mut self, connection_id: &str) {
-        let mut topic_name = "".to_string();
-        let mut index = 0;
-        'outer: for (topic_name_of_consumer, consumer_vec) in self.consumers.iter() {
-            let mut index_of_consumer = 0;
-            for consumer in consumer_vec.iter() {
-                if consumer.consumer_id == connection_id {
-                    topic_name = topic_name_of_consumer.clone();
-                    index = index_of_consumer;
-                    break 'outer;
-                } else {
-                    index_of_consumer += 1;
+        let mut memberships = Vec::new();
+        for (topic_name, groups) in self.consumers.iter() {
+            for (group_id, group) in groups.iter() {
+                if group.member(connection_id).is_some() {
+                    memberships.push((topic_name.clone(), group_id.clone()));
                 }
             }
         }
-        if topic_name.is_empty() {
-            return;
+        for (topic_name, group_id) in memberships {
+            self.remove_consumer(connection_id, &topic_name, &group_id);
         }
-        let consumers_count = self.consumers.get(&topic_name).unwrap().len() as i32;
-        let first_vec = self.consumers.get(&topic_name).unwrap().get(0).unwrap();
-        if consumers_count <= 1 && first_vec.consumer_id == connection_id {
-            self.consumers.get_mut(&topic_name).unwrap().clear();
-            return;
+    }
+
+    pub async fn read_message_from_topic_and_partition(
+        &self,
+        topic: &str,
+        partition: &i32,
+        offset: i32,
+        decompress: bool,
+    ) -> Result<Record, BrokerError> {
+        self.check_partition(topic, *partition)?;
+        match self
+            .messages_store
+            .get_message_by_offset(partition, topic, offset, decompress)
+            .await?
+        {
+            Some(record) => Ok(record),
+            None => Err(BrokerError::OffsetNotAvailable {
+                offset,
+                high_watermark: self.messages_store.high_watermark(partition, topic),
+            }),
         }
-        let mut removed_vec = self.consumers.get_mut(&topic_name).unwrap().remove(index);
-        self.consumers
-            .get_mut(&topic_name)
-            .unwrap()
-            .get_mut(0)
+    }
+
+    /// Reads a batch of records for `FETCH`, see `MessageStore::fetch_records`.
+    pub async fn fetch(&self, request: &Fetch) -> Result<FetchedRecords, BrokerError> {
+        let Fetch {
+            topic_name,
+            partition,
+            offset,
+            max_records,
+            max_bytes,
+            decompress,
+            ..
+        } = request;
+        self.check_partition(topic_name, *partition)?;
+        if *max_records < 1 || *max_bytes < 1 {
+            return Err(BrokerError::invalid_request(
+                "max_records and max_bytes must be positive",
+            ));
+        }
+        let records = self
+            .messages_store
+            .fetch_records(
+                partition,
+                topic_name,
+                *offset,
+                *max_records,
+                *max_bytes,
+                *decompress,
+            )
+            .await?;
+        Ok(FetchedRecords {
+            records,
+            high_watermark: self.messages_store.high_watermark(partition, topic_name),
+        })
+    }
+
+    /// Commits `offset` for the group the consumer joined the topic with, fenced
+    /// by the group's generation and the consumer's ownership of the partition.
+    pub async fn commit_offset(
+        &mut self,
+        connection_id: &str,
+        topic: &str,
+        partition: &i32,
+        offset: i32,
+        generation: i32,
+    ) -> Result<(), BrokerError> {
+        self.check_partition(topic, *partition)?;
+        let group_id = self.consumer_group(connection_id, topic)?;
+        let group = &self.consumers.get(topic).unwrap()[&group_id];
+        if generation != NO_GENERATION {
+            group.check_generation(&group_id, generation)?;
+        }
+        if !group
+            .member(connection_id)
             .unwrap()
             .assigned_partitions
-            .extend(removed_vec.assigned_partitions.drain(..));
+            .contains(partition)
+        {
+            return Err(BrokerError::PartitionNotAssigned {
+                topic: topic.to_string(),
+                partition: *partition,
+            });
+        }
+        if let Err(e) = self
+            .messages_store
+            .commit_offset(partition, topic, &group_id, offset)
+            .await
+        {
+            eprintln!(
+                "Failed to commit offset {} of {}/{}; err = {:?}",
+                offset, topic, partition, e
+            );
+            return Err(e.into());
+        }
+        Ok(())
     }
 
-    pub async fn read_message_from_topic_and_partition(
+    pub async fn offset_for_timestamp(
         &self,
         topic: &str,
         partition: &i32,
-        offset: i32,
-    ) -> Option<Vec<u8>> {
-        self.messages_store
-            .get_message_by_offset(partition, topic, offset)
+        timestamp: i64,
+    ) -> Result<Option<i32>, BrokerError> {
+        self.check_partition(topic, *partition)?;
+        match self
+            .messages_store
+            .get_offset_for_timestamp(partition, topic, timestamp)
             .await
+        {
+            Ok(offset) => Ok(offset),
+            Err(e) => {
+                eprintln!(
+                    "Failed to look up timestamp {} in {}/{}; err = {:?}",
+                    timestamp, topic, partition, e
+                );
+                Err(e.into())
+            }
+        }
     }
 }